@@ -0,0 +1,292 @@
+use log::info;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hd_core::{
+        calculations::{raw_min_max, MinMaxPlateResult, RawExtrema},
+        error::HistDiffError,
+        reader::CellSource,
+        utils::ReadMode,
+    },
+    Hist1D, UserConfig,
+};
+
+use super::{
+    histdiff::{fill_histograms, score_histograms, PlateHistograms},
+    single_pass::read_single_pass,
+    HistDiffRes, PlateScores,
+};
+
+/// Extensions stripped from plate file names, compression first
+const COMPRESSION_EXTENSIONS: &[&str] = &["gz", "gzip", "zst", "zstd"];
+const DATA_EXTENSIONS: &[&str] = &[
+    "tsv", "csv", "txt", "parquet", "pq", "arrow", "ipc", "feather",
+];
+
+/// Which cells the histogram ranges of a batch are computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RangeScope {
+    /// Every plate is binned over its own min/max
+    #[default]
+    PerPlate,
+    /// Every plate is binned over the min/max of the whole campaign
+    ///
+    /// Needs an extra read of every plate before scoring starts and always
+    /// fills histograms in two passes, whatever `UserConfig.read_mode` says.
+    /// Every plate must have every feature scored over the campaign.
+    Campaign,
+}
+
+/// Options for `calculate_scores_batch`
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    pub range_scope: RangeScope,
+    /// How many plates are read and held in memory at once
+    pub max_parallel_plates: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            range_scope: RangeScope::default(),
+            max_parallel_plates: 4,
+        }
+    }
+}
+
+/// Lists the plate files matching a glob pattern such as `"exports/*_cellbycell.tsv"`
+///
+/// Paths come back sorted so batches are reproducible.
+pub fn plates_from_glob(pattern: &str) -> Result<Vec<PathBuf>, HistDiffError> {
+    let paths = glob::glob(pattern).map_err(|err| HistDiffError::Parse(err.to_string()))?;
+
+    let mut plates: Vec<PathBuf> = paths
+        .collect::<Result<Vec<PathBuf>, glob::GlobError>>()
+        .map_err(|err| HistDiffError::Io(err.into()))?;
+    plates.sort();
+
+    return Ok(plates);
+}
+
+/// Calculates HistDiff for every plate of a screening campaign
+///
+/// `config` is used as a template: its block definition, vehicles and options apply
+/// to every plate while `config.path` is replaced by each entry of `plates`.
+/// Each plate is scored against its own vehicle wells.
+///
+/// # returns:
+/// - HistDiffRes keyed by `"{plate}:{well}"`, where the plate name is the file stem.
+///   Plates sharing a stem are told apart by their path relative to their common
+///   directory, and the same file listed twice is a `HistDiffError::DuplicatePlate`.
+///   Failures are reported as `HistDiffError::Plate`.
+pub fn calculate_scores_batch(
+    plates: &[PathBuf],
+    config: &UserConfig,
+    options: &BatchOptions,
+) -> Result<HistDiffRes, HistDiffError> {
+    let names = plate_keys(plates)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.max_parallel_plates.max(1))
+        .build()
+        .map_err(|err| HistDiffError::InvalidConfig(format!("batch thread pool: {}", err)))?;
+
+    let start_t = std::time::Instant::now();
+    if config.verbose {
+        info!("Begin HistDiff batch over {} plates", plates.len());
+    }
+
+    let shared_ranges = match options.range_scope {
+        RangeScope::PerPlate => None,
+        RangeScope::Campaign => Some(pool.install(|| campaign_min_max(plates, &names, config))?),
+    };
+
+    let scores = pool.install(|| {
+        plates
+            .par_iter()
+            .zip(names.par_iter())
+            .map(|(path, plate)| {
+                let plate = plate.clone();
+                let plate_config = UserConfig {
+                    path: path.clone(),
+                    ..config.clone()
+                };
+
+                if config.verbose {
+                    info!("Scoring plate {}", plate);
+                }
+
+                score_plate(&plate_config, shared_ranges.as_ref())
+                    .map(|scores| (plate.clone(), scores))
+                    .map_err(|err| HistDiffError::Plate {
+                        plate,
+                        source: Box::new(err),
+                    })
+            })
+            .collect::<Result<Vec<_>, HistDiffError>>()
+    })?;
+
+    if config.verbose {
+        info!("Finished batch! Time: {:?}", start_t.elapsed());
+    }
+
+    let mut res = HistDiffRes::from_plates(scores, config)?;
+    if let Some(platemap) = &config.platemap {
+        for df in [&mut res.dataframe_scores, &mut res.dataframe_z_scores]
+            .into_iter()
+            .flatten()
+        {
+            platemap.annotate(df)?;
+        }
+    }
+
+    return Ok(res);
+}
+
+/// Scores one plate, either over its own ranges or over the shared campaign ranges
+fn score_plate(
+    config: &UserConfig,
+    shared_ranges: Option<&MinMaxPlateResult>,
+) -> Result<PlateScores, HistDiffError> {
+    let source = CellSource::Path;
+
+    let owned: MinMaxPlateResult;
+    let (min_max, plate): (&MinMaxPlateResult, PlateHistograms) =
+        match (shared_ranges, config.read_mode) {
+            (Some(min_max), _) => (min_max, fill_histograms(config, &source, min_max)?),
+            (None, ReadMode::SinglePass) => {
+                let (min_max, plate) = read_single_pass(config, &source)?;
+                owned = min_max;
+                (&owned, plate)
+            }
+            (None, ReadMode::TwoPass) => {
+                owned = raw_min_max(config, &source)?.finalize(config)?;
+                let plate = fill_histograms(config, &source, &owned)?;
+                (&owned, plate)
+            }
+        };
+
+    let scores = score_histograms(config, min_max, plate)?;
+    return Ok(scores.with_report(min_max));
+}
+
+/// Merges the extrema of every plate into one set of campaign ranges
+///
+/// Fails with `HistDiffError::MissingFeature` for a plate lacking a scored campaign feature.
+fn campaign_min_max(
+    plates: &[PathBuf],
+    names: &[String],
+    config: &UserConfig,
+) -> Result<MinMaxPlateResult, HistDiffError> {
+    let extrema: Vec<RawExtrema> = plates
+        .par_iter()
+        .zip(names.par_iter())
+        .map(|(path, plate)| {
+            let plate_config = UserConfig {
+                path: path.clone(),
+                ..config.clone()
+            };
+            raw_min_max(&plate_config, &CellSource::Path).map_err(|err| HistDiffError::Plate {
+                plate: plate.clone(),
+                source: Box::new(err),
+            })
+        })
+        .collect::<Result<Vec<_>, HistDiffError>>()?;
+
+    let plate_features: Vec<HashSet<String>> = extrema
+        .iter()
+        .map(|plate_extrema| plate_extrema.features.iter().cloned().collect())
+        .collect();
+
+    let mut merged = RawExtrema::new(Vec::new(), config);
+    for plate_extrema in extrema {
+        merged.merge(plate_extrema);
+    }
+    let min_max = merged.finalize(config)?;
+
+    for (plate, features) in names.iter().zip(&plate_features) {
+        if let Some(feature) = min_max.features.iter().find(|f| !features.contains(*f)) {
+            return Err(HistDiffError::Plate {
+                plate: plate.clone(),
+                source: Box::new(HistDiffError::MissingFeature(feature.clone())),
+            });
+        }
+    }
+
+    return Ok(min_max);
+}
+
+/// Key of every plate in a batch, in the order of `plates`
+///
+/// Plates are keyed by their file stem. When stems collide, the colliding plates are keyed
+/// by their path relative to the directory they share instead.
+fn plate_keys(plates: &[PathBuf]) -> Result<Vec<String>, HistDiffError> {
+    let stems: Vec<String> = plates.iter().map(|path| plate_name(path)).collect();
+    let mut by_stem: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, stem) in stems.iter().enumerate() {
+        by_stem.entry(stem.as_str()).or_default().push(i);
+    }
+
+    let mut keys = stems.clone();
+    for indices in by_stem.values().filter(|indices| indices.len() > 1) {
+        let paths: Vec<&Path> = indices.iter().map(|&i| plates[i].as_path()).collect();
+        let shared = shared_dir(&paths);
+        for (&i, path) in indices.iter().zip(paths) {
+            let relative = path.strip_prefix(&shared).unwrap_or(path);
+            let key = relative.with_file_name(&stems[i]);
+            keys[i] = key
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for key in &keys {
+        if !seen.insert(key.as_str()) {
+            return Err(HistDiffError::DuplicatePlate(key.clone()));
+        }
+    }
+
+    return Ok(keys);
+}
+
+/// Longest directory shared by the parents of `paths`
+fn shared_dir(paths: &[&Path]) -> PathBuf {
+    let mut shared: Vec<_> = paths[0]
+        .parent()
+        .map(|dir| dir.components().collect())
+        .unwrap_or_default();
+    for path in &paths[1..] {
+        let dir: Vec<_> = path
+            .parent()
+            .map(|dir| dir.components().collect())
+            .unwrap_or_default();
+        let common = shared.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+        shared.truncate(common);
+    }
+    return shared.iter().collect();
+}
+
+/// File name of a plate without its data and compression extensions
+/// (`run.2024.01.tsv.gz` => `run.2024.01`)
+fn plate_name(path: &Path) -> String {
+    let mut name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    for extensions in [COMPRESSION_EXTENSIONS, DATA_EXTENSIONS] {
+        if let Some((stem, ext)) = name.rsplit_once('.') {
+            if !stem.is_empty() && extensions.contains(&ext.to_lowercase().as_str()) {
+                name = stem.to_string();
+            }
+        }
+    }
+
+    return name;
+}
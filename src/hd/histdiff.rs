@@ -1,20 +1,32 @@
 use core::f64;
 use dashmap::DashMap;
-use log::{info, trace};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use log::{info, trace, warn};
+use polars::prelude::{DataFrame, IntoLazy, LazyFrame};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
 use std::{
     collections::{HashMap, HashSet},
-    error::Error,
-    fs::File,
-    io::BufReader,
-    usize,
+    slice,
 };
 
 use crate::{
-    get_min_max_plate, hd_core::utils::clean_well_names, hist_square_diff, Hist1D, UserConfig,
+    hd_core::{
+        calculations::{min_max_from_source, MinMaxPlateResult},
+        cells::LowCellAction,
+        error::HistDiffError,
+        metrics::{metric_scores, Metric, MetricSpec},
+        pooling::RejectedVehicle,
+        reader::CellSource,
+        significance::{benjamini_hochberg, empirical_p_value, Resampler, SignificanceOptions},
+        standardize::{median, robust_z_scores},
+        utils::{clean_well_names, well_set, ReadMode, ScoreFactor},
+        well::WellId,
+    },
+    hist_square_diff, Hist1D, HistCounts, UserConfig,
 };
 
-use super::HistDiffRes;
+use super::{single_pass::read_single_pass, HistDiffRes, PlateScores, WellCounts, WellScores};
 
 /// Calculates HistDiff
 ///
@@ -24,153 +36,350 @@ use super::HistDiffRes;
 /// # returns:
 /// - HistDiffRes => See documentation on HistDiffRes for options
 #[allow(dead_code)]
-pub fn calculate_scores(config: &UserConfig) -> Result<HistDiffRes, Box<dyn Error>> {
+pub fn calculate_scores(config: &UserConfig) -> Result<HistDiffRes, HistDiffError> {
+    return score_source(config, &CellSource::Path);
+}
+
+/// Calculates HistDiff from cell data that is already in a polars `DataFrame`
+///
+/// Same pipeline as `calculate_scores`; `config.path` and `config.input_format` are ignored.
+pub fn calculate_scores_df(
+    df: &DataFrame,
+    config: &UserConfig,
+) -> Result<HistDiffRes, HistDiffError> {
+    return score_source(config, &CellSource::Data(Box::new(df.clone())));
+}
+
+/// Calculates HistDiff from a polars `LazyFrame`
+///
+/// The query is projected to the ID and feature columns and collected in row slices,
+/// so scans with slice pushdown (Parquet, IPC) never materialize the whole table.
+/// `ReadMode::TwoPass` runs the query twice; use `ReadMode::SinglePass` for expensive queries.
+pub fn calculate_scores_lazy(
+    lf: LazyFrame,
+    config: &UserConfig,
+) -> Result<HistDiffRes, HistDiffError> {
+    return score_source(config, &CellSource::Frame(Box::new(lf)));
+}
+
+/// Runs the full HistDiff pipeline over one source of cell data
+fn score_source(config: &UserConfig, source: &CellSource) -> Result<HistDiffRes, HistDiffError> {
     if config.verbose {
         info!("Begin HistDiff Calculations");
     }
 
-    let plate_def = &config.plate_def;
+    let (min_max, plate) = match config.read_mode {
+        ReadMode::TwoPass => {
+            let min_max = min_max_from_source(config, source)?;
+            let plate = fill_histograms(config, source, &min_max)?;
+            (min_max, plate)
+        }
+        ReadMode::SinglePass => read_single_pass(config, source)?,
+    };
+
+    let scores = score_histograms(config, &min_max, plate)?;
+
+    let mut res = HistDiffRes::from_plate(scores.with_report(&min_max), config)?;
+    if let Some(platemap) = &config.platemap {
+        for df in [&mut res.dataframe_scores, &mut res.dataframe_z_scores]
+            .into_iter()
+            .flatten()
+        {
+            platemap.annotate(df)?;
+        }
+    }
 
-    let min_max = get_min_max_plate(config)?;
-    let min_max_vec = min_max.min_max;
-    let features = min_max.features;
+    return Ok(res);
+}
 
-    let file = File::open(&config.path)?;
-    let reader = BufReader::new(file);
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(true)
-        .flexible(true)
-        .from_reader(reader);
+/// well => feature => histogram
+pub(crate) type WellHistograms = HashMap<String, HashMap<String, Hist1D>>;
 
-    let headers = csv_reader.headers()?.clone();
-    let headers_len = headers.len();
-    let headers_vec: Vec<String> = headers.into_iter().map(|x| x.to_string()).collect();
+/// Histograms of every well of one plate
+pub(crate) struct PlateHistograms {
+    /// well => feature => histogram
+    pub histograms: WellHistograms,
+    /// well => number of cell rows read for it
+    pub cell_counts: HashMap<String, usize>,
+}
 
-    let id_col_idx: &Vec<usize> = &config
-        .id_cols
-        .iter()
-        .map(|col| headers.iter().position(|h| h == col))
-        .collect::<Option<Vec<_>>>()
-        .ok_or("ID Column not found in headers")?;
-
-    let useless_idx: Option<Vec<usize>> = config.useless_cols.as_ref().map(|cols| {
-        cols.iter()
-            .filter_map(|i| headers_vec.iter().position(|h| h == i))
-            .collect()
-    });
+/// Counts the rows of every well of `plate_def` in a batch
+pub(crate) fn count_cells(
+    cell_counts: &mut HashMap<String, usize>,
+    wells: &[String],
+    plate_def: &HashSet<String>,
+) {
+    for well in wells {
+        if let Some(count) = cell_counts.get_mut(well) {
+            *count += 1;
+        } else if plate_def.contains(well) {
+            cell_counts.insert(well.clone(), 1);
+        }
+    }
+}
+
+/// Reads the cell data and bins every well into the ranges from `min_max`
+pub(crate) fn fill_histograms(
+    config: &UserConfig,
+    source: &CellSource,
+    min_max: &MinMaxPlateResult,
+) -> Result<PlateHistograms, HistDiffError> {
+    let plate_def: HashSet<String> = well_set(&config.plate_def);
+    let out_of_range = config.range.out_of_range;
+
+    let mut reader = source.open(config)?;
 
-    let feature_idx: Vec<usize> = (0..headers_len)
-        .filter(|i| {
-            !id_col_idx.contains(i)
-                && useless_idx
-                    .as_ref()
-                    .map_or(true, |useless| !useless.contains(i))
+    // batch column of every feature that survived the min max step
+    let feature_pos: Vec<usize> = min_max
+        .min_max
+        .iter()
+        .map(|(feat, _)| {
+            reader
+                .features
+                .iter()
+                .position(|f| f == feat)
+                .ok_or_else(|| HistDiffError::MissingFeature(feat.clone()))
         })
-        .collect();
+        .collect::<Result<_, HistDiffError>>()?;
+
+    let templates: Vec<Hist1D> = min_max.empty_histograms()?;
 
-    let histograms: DashMap<String, DashMap<String, Hist1D>> = DashMap::new();
+    // well => histogram, one map per feature so features can be filled in parallel
+    let mut per_feature: Vec<HashMap<String, Hist1D>> = vec![HashMap::new(); min_max.min_max.len()];
+    let mut cell_counts: HashMap<String, usize> = HashMap::new();
 
     let start_t = std::time::Instant::now();
     if config.verbose {
         info!("Begin reading cell data file.");
     }
 
-    for record in csv_reader.records() {
-        let rec = record?;
-        if rec.len() != headers_len {
-            continue;
-        }
+    while let Some(batch) = reader.next_batch()? {
+        count_cells(&mut cell_counts, &batch.wells, &plate_def);
+
+        per_feature
+            .par_iter_mut()
+            .zip(templates.par_iter())
+            .zip(feature_pos.par_iter())
+            .for_each(|((hists, template), &pos)| {
+                for (well, &value) in batch.wells.iter().zip(&batch.columns[pos]) {
+                    if !plate_def.contains(well.as_str()) {
+                        continue;
+                    }
 
-        // in case there are multiple id columns merge them else use use the string
-        let curr_well = &config
-            .id_cols
-            .iter()
-            .map(|i| {
-                rec.get(headers_vec.iter().position(|h| h == i).unwrap())
-                    .unwrap()
-            })
-            .collect::<Vec<&str>>()
-            .join("_");
+                    match hists.get_mut(well) {
+                        Some(hist) => hist.fill_with(&[value], out_of_range),
+                        None => {
+                            let mut hist = template.clone();
+                            hist.fill_with(&[value], out_of_range);
+                            hists.insert(well.clone(), hist);
+                        }
+                    }
+                }
+            });
+    }
 
-        if !plate_def.contains(&curr_well) {
-            continue;
+    if config.verbose {
+        info!("Time to read file: {:?}", start_t.elapsed());
+    }
+
+    return Ok(PlateHistograms {
+        histograms: by_well(&min_max.features, per_feature),
+        cell_counts,
+    });
+}
+
+/// Turns one well => histogram map per feature into well => feature => histogram
+pub(crate) fn by_well(
+    features: &[String],
+    per_feature: Vec<HashMap<String, Hist1D>>,
+) -> WellHistograms {
+    let mut histograms: WellHistograms = HashMap::new();
+    for (feat, hists) in features.iter().zip(per_feature) {
+        for (well, hist) in hists {
+            histograms
+                .entry(well)
+                .or_default()
+                .insert(feat.clone(), hist);
         }
+    }
 
-        let feature_values: Vec<(&str, f64)> = feature_idx
-            .par_iter()
-            .map(|&i| {
-                let feat_name = headers_vec[i].as_str();
-                let value = rec.get(i).unwrap().parse::<f64>().unwrap_or(f64::NAN);
-                return (feat_name, value);
-            })
-            .collect();
+    return histograms;
+}
 
-        histograms.entry(curr_well.clone()).or_insert_with(|| {
-            let feature_map: DashMap<String, Hist1D> = DashMap::new();
-            let _ = &features.par_iter().for_each(|feat| {
-                let (_, vals) = min_max_vec.iter().find(|(f, _)| f == feat).unwrap();
-                let new_hist = Hist1D::new(config.nbins.clone(), vals.xlow, vals.xhigh);
-                feature_map.insert(feat.clone(), new_hist);
-            });
+/// Finds the config wells that never showed up in the data
+///
+/// # returns:
+/// - (vehicle controls without data, wells of the plate/block definitions without data),
+///   both in plate order
+pub(crate) fn unseen_wells<'a>(
+    config: &UserConfig,
+    seen: impl Iterator<Item = &'a String>,
+) -> (Vec<String>, Vec<String>) {
+    let seen: HashSet<&String> = seen.collect();
+    let unseen = |wells: HashSet<String>| -> Vec<String> {
+        let mut wells: Vec<String> = wells.into_iter().filter(|w| !seen.contains(w)).collect();
+        wells.sort_by_key(|w| (WellId::parse(w).ok(), w.clone()));
+        wells
+    };
+
+    let mut config_wells = well_set(&config.plate_def);
+    for group in &config.block_def {
+        config_wells.extend(well_set(group));
+    }
 
-            return feature_map;
-        });
+    let vehicles = unseen(well_set(&config.vehicle_cntrls));
+    let wells = unseen(config_wells);
 
-        let well_histogram = histograms.get_mut(curr_well).unwrap();
-        feature_values.par_iter().for_each(|(feat, val)| {
-            if let Some(mut hist) = well_histogram.get_mut(*feat) {
-                hist.fill(&[*val]);
-            }
-        });
+    if config.verbose && !vehicles.is_empty() {
+        warn!("Vehicle controls without data: {:?}", vehicles);
+    }
+    if config.verbose && !wells.is_empty() {
+        info!("{} configured wells have no data", wells.len());
     }
 
-    let histograms: HashMap<String, HashMap<String, Hist1D>> = histograms
-        .into_iter()
-        .map(|(well_id, well_hist)| {
-            let hists = well_hist.into_iter().collect();
-            (well_id, hists)
-        })
-        .collect();
+    return (vehicles, wells);
+}
 
+/// Pools the vehicle controls of every block and scores each well against them
+///
+/// Every feature of `min_max` is scored; features constant across the plate score 0.
+/// Wells with fewer cells than `config.cells.min_cells` are flagged or masked. With
+/// `config.leave_one_out` every vehicle is scored against the pool of the other vehicles.
+///
+/// # returns:
+/// - well => feature => HistDiff score, plus the factor used for every well
+/// - errors when a block has no wells with data or none of its wells are vehicle controls
+pub(crate) fn score_histograms(
+    config: &UserConfig,
+    min_max: &MinMaxPlateResult,
+    plate: PlateHistograms,
+) -> Result<PlateScores, HistDiffError> {
     if config.verbose {
-        info!("Time to read file: {:?}", start_t.elapsed());
         info!("Begin HistDiff histogram calculations and adjustments.");
     }
+    config.smoothing.validate()?;
+    config.factor.validate()?;
+    if config.metrics.is_empty() {
+        return Err(HistDiffError::InvalidConfig(
+            "at least one metric must be selected".to_string(),
+        ));
+    }
+    if let Some(options) = &config.significance {
+        options.validate()?;
+    }
+    config.pooling.validate()?;
+
+    let PlateHistograms {
+        histograms,
+        cell_counts,
+    } = plate;
+    let (unseen_vehicles, unseen_wells) = unseen_wells(config, histograms.keys());
+    let qc: WellCounts = histograms
+        .iter()
+        .map(|(well, hists)| {
+            let counts = hists
+                .iter()
+                .map(|(feat, hist)| (feat.clone(), hist.qc_counts()))
+                .collect();
+            (well.clone(), counts)
+        })
+        .collect();
+    let nbins: HashMap<String, usize> = histograms
+        .values()
+        .flat_map(|hists| hists.iter().map(|(feat, hist)| (feat.clone(), hist.nbins)))
+        .collect();
 
     // NOTE: HistDiff calculation process below
     let start_t = std::time::Instant::now();
 
-    let mut hd_scores: HashMap<String, HashMap<String, f64>> = HashMap::new();
-    for group in &config.block_def {
+    let vehicles: Vec<String> = clean_well_names(&config.vehicle_cntrls);
+    let features: &[String] = &min_max.features;
+    let constant: HashSet<&str> = min_max
+        .constant_features
+        .iter()
+        .map(|feat| feat.as_str())
+        .collect();
+
+    let mut hd_scores: WellScores = HashMap::new();
+    let mut factors: HashMap<String, f64> = HashMap::new();
+    let mut vehicle_cells: HashMap<String, usize> = HashMap::new();
+    let mut p_values: WellScores = HashMap::new();
+    let mut vehicle_null: WellScores = HashMap::new();
+    let mut rejected_vehicles: Vec<RejectedVehicle> = Vec::new();
+    // (wells with data, vehicles with data) of every block
+    let mut blocks: Vec<(Vec<String>, Vec<String>)> = Vec::new();
+    for (block, group) in config.block_def.iter().enumerate() {
         // clean the well names
-        let select_wells: HashSet<String> = clean_well_names(group).into_iter().collect();
+        let select_wells: HashSet<String> = well_set(group);
 
-        let mut hd_group: HashMap<String, HashMap<String, Hist1D>> = histograms
+        let mut hd_group: WellHistograms = histograms
             .iter()
             .filter(|(well, _)| select_wells.contains(*well))
             .map(|(well, well_hist)| (well.clone(), well_hist.clone()))
             .collect();
 
-        let mut cntr_hists: HashMap<String, Hist1D> = HashMap::new();
+        if hd_group.is_empty() {
+            return Err(HistDiffError::EmptyBlock { block });
+        }
 
-        for feat in &features {
-            let mut sum_hist: Option<Hist1D> = None;
+        let present: Vec<String> = vehicles
+            .iter()
+            .filter(|well| hd_group.contains_key(*well))
+            .cloned()
+            .collect();
+        if present.is_empty() {
+            return Err(HistDiffError::MissingControls { block });
+        }
 
-            for well in &config.vehicle_cntrls {
-                if let Some(hist) = hd_group.get(well).and_then(|hists| hists.get(feat)) {
-                    if let Some(ref mut sums) = sum_hist {
-                        sums.add(hist);
-                    } else {
-                        sum_hist = Some(hist.clone());
-                    }
+        // vehicles that make up the control pool
+        let block_vehicles: Vec<String> = match config.pooling.reject_above {
+            Some(threshold) => {
+                let (kept, rejected) = reject_vehicles(
+                    config,
+                    present,
+                    &histograms,
+                    &cell_counts,
+                    features,
+                    &constant,
+                    threshold,
+                )?;
+                if kept.is_empty() {
+                    return Err(HistDiffError::MissingControls { block });
                 }
+                rejected_vehicles.extend(rejected);
+                kept
             }
+            None => present,
+        };
+
+        let cells = |well: &String| cell_counts.get(well).copied().unwrap_or(0);
+        let pooled: usize = block_vehicles.iter().map(cells).sum();
+        vehicle_cells.extend(hd_group.keys().map(|well| (well.clone(), pooled)));
 
-            if let Some(sums) = sum_hist {
-                cntr_hists.insert(feat.clone(), sums);
+        let mut block_factors: HashMap<String, f64> = match config.factor {
+            ScoreFactor::Constant(factor) => {
+                hd_group.keys().map(|well| (well.clone(), factor)).collect()
             }
-        }
+            ScoreFactor::CellCountRatio => hd_group
+                .keys()
+                .map(|well| {
+                    let ratio = cell_count_ratio(cells(well), pooled, block_vehicles.len());
+                    (well.clone(), ratio)
+                })
+                .collect(),
+        };
+
+        let cntr_hists: HashMap<String, Hist1D> = features
+            .iter()
+            .filter_map(|feat| {
+                vehicle_pool(config, &block_vehicles, &histograms, feat, None)
+                    .transpose()
+                    .map(|pool| pool.map(|pool| (feat.clone(), pool)))
+            })
+            .collect::<Result<_, HistDiffError>>()?;
+
+        // resampling needs the pooled counts before smoothing
+        let raw_cntrl = cntr_hists.clone();
 
         if config.verbose {
             info!("Adding control sum into HD group");
@@ -182,7 +391,7 @@ pub fn calculate_scores(config: &UserConfig) -> Result<HistDiffRes, Box<dyn Erro
         }
         for histograms in hd_group.values_mut() {
             for feats in histograms.values_mut() {
-                feats.smooth(0.25);
+                feats.smooth_with(&config.smoothing);
                 feats.normalize();
             }
         }
@@ -191,10 +400,10 @@ pub fn calculate_scores(config: &UserConfig) -> Result<HistDiffRes, Box<dyn Erro
             info!("Calculating scores!");
         }
 
-        let per_feature_score: Vec<HashMap<String, HashMap<String, f64>>> = features
+        let per_feature_score: Vec<WellScores> = features
             .par_iter()
-            .map(|feat| {
-                let mut local_scores: HashMap<String, HashMap<String, f64>> = HashMap::new();
+            .map(|feat| -> Result<_, HistDiffError> {
+                let mut local_scores: WellScores = HashMap::new();
 
                 // lets get the exp wells
                 let mut exp_wells: Vec<Vec<f64>> = Vec::new();
@@ -214,42 +423,550 @@ pub fn calculate_scores(config: &UserConfig) -> Result<HistDiffRes, Box<dyn Erro
                 let cntrl_row = hd_group
                     .get("CNTRL")
                     .and_then(|hist| hist.get(feat))
-                    .expect("CNTRL row not found!")
+                    .ok_or(HistDiffError::MissingControls { block })?
                     .data()
                     .1
                     .to_vec();
 
-                let factor = 1.0;
-                let score = hist_square_diff(&exp_wells, &cntrl_row, factor)
-                    .expect("Unable to calculate HistDiff score");
-
-                for (well_id, hd_value) in well_ids.iter().zip(score.into_iter()) {
-                    local_scores
-                        .entry(well_id.clone())
-                        .or_insert_with(HashMap::new)
-                        .insert(feat.clone(), hd_value);
+                let well_factors: Vec<f64> = well_ids.iter().map(|w| block_factors[w]).collect();
+                for spec in &config.metrics {
+                    let score = match constant.contains(feat.as_str()) {
+                        // identical distributions everywhere, whatever the factor
+                        true => vec![0.0; well_ids.len()],
+                        false => score_rows(config, spec, &exp_wells, &well_factors, &cntrl_row)?,
+                    };
+
+                    let column = spec.column_name(feat);
+                    for (well_id, hd_value) in well_ids.iter().zip(score) {
+                        local_scores
+                            .entry(well_id.clone())
+                            .or_default()
+                            .insert(column.clone(), hd_value);
+                    }
                 }
 
-                return local_scores;
+                return Ok(local_scores);
             })
-            .collect();
+            .collect::<Result<_, HistDiffError>>()?;
 
         for local_scores in per_feature_score {
             for (well_id, feat_map) in local_scores {
-                hd_scores
-                    .entry(well_id)
-                    .or_insert_with(HashMap::new)
-                    .extend(feat_map);
+                hd_scores.entry(well_id).or_default().extend(feat_map);
+            }
+        }
+
+        let loo_scores = match config.standardize || config.leave_one_out {
+            true => leave_one_out_scores(
+                config,
+                &block_vehicles,
+                &histograms,
+                &cell_counts,
+                features,
+                &constant,
+            )?,
+            false => HashMap::new(),
+        };
+
+        // vehicles without another vehicle to compare with get NaN instead of a self comparison
+        if config.leave_one_out {
+            for well in &block_vehicles {
+                let loo = loo_scores.get(well);
+                if let Some(scores) = hd_scores.get_mut(well) {
+                    for (column, score) in scores.iter_mut() {
+                        *score = loo.and_then(|l| l.get(column)).copied().unwrap_or(f64::NAN);
+                    }
+                }
+                let others = pooled - cells(well);
+                block_factors.insert(
+                    well.clone(),
+                    leave_one_out_factor(config, cells(well), others, block_vehicles.len() - 1),
+                );
+                vehicle_cells.insert(well.clone(), others);
+            }
+        }
+
+        if let Some(options) = &config.significance {
+            if config.verbose {
+                info!(
+                    "Resampling {} null scores per well and feature",
+                    options.iterations
+                );
             }
+            let block_p = block_p_values(
+                config,
+                options,
+                &histograms,
+                &raw_cntrl,
+                &block_vehicles,
+                &block_factors,
+                &constant,
+                &hd_scores,
+            )?;
+            p_values.extend(block_p);
         }
+
+        if config.standardize {
+            vehicle_null.extend(loo_scores);
+        }
+        let mut block_wells: Vec<String> = block_factors.keys().cloned().collect();
+        block_wells.sort();
+        blocks.push((block_wells, block_vehicles));
+        factors.extend(block_factors);
     }
 
+    let low_cell_wells = low_cell_wells(
+        config,
+        &mut hd_scores,
+        &mut p_values,
+        &mut vehicle_null,
+        &cell_counts,
+    );
+    let q_values = q_values(&p_values);
+    let z_scores: WellScores = match config.standardize {
+        true => z_scores(&hd_scores, &vehicle_null, &blocks),
+        false => HashMap::new(),
+    };
+    let cell_counts: HashMap<String, usize> = cell_counts
+        .into_iter()
+        .filter(|(well, _)| hd_scores.contains_key(well))
+        .collect();
+
     if config.verbose {
         info!("Wrapping things up!");
         info!("Finished calculations! Time: {:?}", start_t.elapsed());
     }
 
-    let res = HistDiffRes::new(hd_scores);
+    return Ok(PlateScores {
+        scores: hd_scores,
+        factors,
+        qc,
+        nbins,
+        cell_counts,
+        vehicle_cells,
+        low_cell_wells,
+        p_values,
+        q_values,
+        vehicle_null,
+        z_scores,
+        rejected_vehicles,
+        excluded_columns: Vec::new(),
+        problematic_features: Vec::new(),
+        unseen_vehicles,
+        unseen_wells,
+    });
+}
+
+/// Finds the scored wells with fewer cells than `config.cells.min_cells`
+///
+/// Their scores, p-values and leave-one-out vehicle scores are replaced with `NaN`
+/// when the action is `LowCellAction::Mask`.
+///
+/// # returns:
+/// - the under-populated wells in plate order
+fn low_cell_wells(
+    config: &UserConfig,
+    hd_scores: &mut WellScores,
+    p_values: &mut WellScores,
+    vehicle_null: &mut WellScores,
+    cell_counts: &HashMap<String, usize>,
+) -> Vec<String> {
+    let options = &config.cells;
+    let mut low: Vec<String> = hd_scores
+        .keys()
+        .filter(|well| options.is_low(cell_counts.get(*well).copied().unwrap_or(0)))
+        .cloned()
+        .collect();
+    low.sort_by_key(|w| (WellId::parse(w).ok(), w.clone()));
+
+    if options.action == LowCellAction::Mask {
+        for well in &low {
+            let masked = [
+                hd_scores.get_mut(well),
+                p_values.get_mut(well),
+                vehicle_null.get_mut(well),
+            ];
+            for values in masked.into_iter().flatten() {
+                values.values_mut().for_each(|value| *value = f64::NAN);
+            }
+        }
+    }
+
+    if config.verbose && !low.is_empty() {
+        warn!(
+            "{} wells have fewer than {} cells: {:?}",
+            low.len(),
+            options.min_cells,
+            low
+        );
+    }
 
-    return Ok(res);
+    return low;
+}
+
+/// Scores experimental rows against a control row with one metric
+///
+/// `factors` holds the HistDiff scaling factor of every row (see `ScoreFactor`).
+fn score_rows(
+    config: &UserConfig,
+    spec: &MetricSpec,
+    exp_rows: &[Vec<f64>],
+    factors: &[f64],
+    cntrl_row: &[f64],
+) -> Result<Vec<f64>, HistDiffError> {
+    match spec.metric {
+        // the original HistDiff score keeps its scaling factor
+        Metric::HistDiff => {
+            let score = match config.factor {
+                ScoreFactor::Constant(factor) => hist_square_diff(exp_rows, cntrl_row, factor)?,
+                ScoreFactor::CellCountRatio => exp_rows
+                    .iter()
+                    .zip(factors)
+                    .map(|(row, &factor)| {
+                        hist_square_diff(slice::from_ref(row), cntrl_row, factor)
+                            .map(|score| score[0])
+                    })
+                    .collect::<Result<Vec<f64>, HistDiffError>>()?,
+            };
+            return Ok(match spec.signed {
+                true => score,
+                false => score.into_iter().map(f64::abs).collect(),
+            });
+        }
+        _ => metric_scores(&spec.metric, exp_rows, cntrl_row, spec.signed),
+    }
+}
+
+/// Resampling p-values of every scored well of a block (see `SignificanceOptions`)
+///
+/// # params:
+/// - histograms => raw histograms of the plate
+/// - raw_cntrl => feature => pooled vehicle histogram of the block before smoothing
+/// - vehicles => vehicles of the pool, each is resampled against the other vehicles
+///   with `config.leave_one_out`
+/// - scores => observed scores, only the wells of `factors` are resampled
+///
+/// # returns:
+/// - well => score column => p-value; features constant across the plate get 1.0
+fn block_p_values(
+    config: &UserConfig,
+    options: &SignificanceOptions,
+    histograms: &WellHistograms,
+    raw_cntrl: &HashMap<String, Hist1D>,
+    vehicles: &[String],
+    factors: &HashMap<String, f64>,
+    constant: &HashSet<&str>,
+    scores: &WellScores,
+) -> Result<WellScores, HistDiffError> {
+    let prepare = |mut hist: Hist1D, counts: Vec<f64>| -> Vec<f64> {
+        hist.counts = counts;
+        hist.smooth_with(&config.smoothing);
+        hist.normalize();
+        hist.counts
+    };
+
+    let tests: Vec<(&String, &String, &Hist1D, &Hist1D)> = factors
+        .keys()
+        .filter_map(|well| histograms.get(well).map(|hists| (well, hists)))
+        .flat_map(|(well, hists)| {
+            hists.iter().filter_map(move |(feat, hist)| {
+                raw_cntrl.get(feat).map(|pool| (well, feat, hist, pool))
+            })
+        })
+        .collect();
+
+    let per_test: Vec<(&String, Vec<(String, f64)>)> = tests
+        .par_iter()
+        .map(|&(well, feat, hist, pool)| -> Result<_, HistDiffError> {
+            let columns = config.metrics.iter().map(|spec| spec.column_name(feat));
+            if constant.contains(feat.as_str()) {
+                return Ok((well, columns.map(|column| (column, 1.0)).collect()));
+            }
+            let pool = match config.leave_one_out && vehicles.contains(well) {
+                true => vehicle_pool(config, vehicles, histograms, feat, Some(well))?,
+                false => Some(pool.clone()),
+            };
+            let Some(pool) = pool else {
+                return Ok((well, columns.map(|column| (column, f64::NAN)).collect()));
+            };
+
+            let mut resampler = Resampler::new(
+                options,
+                &hist.counts,
+                &pool.counts,
+                &format!("{}:{}", well, feat),
+            );
+            let draws: Vec<(Vec<f64>, Vec<f64>)> = match resampler.is_empty() {
+                true => Vec::new(),
+                false => (0..options.iterations)
+                    .map(|_| {
+                        let (exp, cntrl) = resampler.draw();
+                        (prepare(hist.clone(), exp), prepare(pool.clone(), cntrl))
+                    })
+                    .collect(),
+            };
+
+            let factor = [factors[well]];
+            let mut p: Vec<(String, f64)> = Vec::new();
+            for spec in &config.metrics {
+                let column = spec.column_name(feat);
+                let null: Vec<f64> = draws
+                    .iter()
+                    .map(|(exp, cntrl)| {
+                        score_rows(config, spec, slice::from_ref(exp), &factor, cntrl)
+                            .map(|score| score[0])
+                    })
+                    .collect::<Result<_, HistDiffError>>()?;
+                let observed = scores
+                    .get(well)
+                    .and_then(|s| s.get(&column))
+                    .copied()
+                    .unwrap_or(f64::NAN);
+                p.push((column, empirical_p_value(observed, &null)));
+            }
+
+            return Ok((well, p));
+        })
+        .collect::<Result<_, HistDiffError>>()?;
+
+    let mut p_values: WellScores = HashMap::new();
+    for (well, p) in per_test {
+        p_values.entry(well.clone()).or_default().extend(p);
+    }
+
+    return Ok(p_values);
+}
+
+/// Benjamini–Hochberg q-values of every score column over the wells of the plate
+fn q_values(p_values: &WellScores) -> WellScores {
+    let mut by_column: HashMap<&String, (Vec<&String>, Vec<f64>)> = HashMap::new();
+    for (well, columns) in p_values {
+        for (column, &p) in columns {
+            let (wells, p_column) = by_column.entry(column).or_default();
+            wells.push(well);
+            p_column.push(p);
+        }
+    }
+
+    let mut q_values: WellScores = HashMap::new();
+    for (column, (wells, p_column)) in by_column {
+        for (well, q) in wells.into_iter().zip(benjamini_hochberg(&p_column)) {
+            q_values
+                .entry(well.clone())
+                .or_default()
+                .insert(column.clone(), q);
+        }
+    }
+
+    return q_values;
+}
+
+/// Pooled raw vehicle histogram of a feature (see `PoolingMethod`), `except` left out
+///
+/// # returns:
+/// - `None` when none of the remaining vehicles has data for the feature
+fn vehicle_pool(
+    config: &UserConfig,
+    vehicles: &[String],
+    histograms: &WellHistograms,
+    feat: &String,
+    except: Option<&String>,
+) -> Result<Option<Hist1D>, HistDiffError> {
+    let hists: Vec<&Hist1D> = vehicles
+        .iter()
+        .filter(|well| Some(*well) != except)
+        .filter_map(|well| histograms.get(well)?.get(feat))
+        .collect();
+
+    return config.pooling.method.pool(&hists);
+}
+
+/// Scores every vehicle well of a block against the pool of the other vehicles
+///
+/// # params:
+/// - vehicles => vehicle wells of the control pool
+///
+/// # returns:
+/// - vehicle well => score column => score, empty when the block has a single vehicle
+fn leave_one_out_scores(
+    config: &UserConfig,
+    vehicles: &[String],
+    histograms: &WellHistograms,
+    cell_counts: &HashMap<String, usize>,
+    features: &[String],
+    constant: &HashSet<&str>,
+) -> Result<WellScores, HistDiffError> {
+    let mut scores: WellScores = HashMap::new();
+    if vehicles.len() < 2 {
+        return Ok(scores);
+    }
+    let pooled: usize = vehicles
+        .iter()
+        .map(|well| cell_counts.get(well).copied().unwrap_or(0))
+        .sum();
+
+    for well in vehicles {
+        let Some(hists) = histograms.get(well) else {
+            continue;
+        };
+        let cells = cell_counts.get(well).copied().unwrap_or(0);
+        let factor = leave_one_out_factor(config, cells, pooled - cells, vehicles.len() - 1);
+
+        let per_feature: Vec<Vec<(String, f64)>> = features
+            .par_iter()
+            .map(|feat| -> Result<_, HistDiffError> {
+                let Some(hist) = hists.get(feat) else {
+                    return Ok(Vec::new());
+                };
+                if constant.contains(feat.as_str()) {
+                    let columns = config.metrics.iter().map(|spec| spec.column_name(feat));
+                    return Ok(columns.map(|column| (column, 0.0)).collect());
+                }
+
+                let Some(mut others) =
+                    vehicle_pool(config, vehicles, histograms, feat, Some(well))?
+                else {
+                    return Ok(Vec::new());
+                };
+                if others.counts.iter().sum::<f64>() <= 0.0 {
+                    return Ok(Vec::new());
+                }
+
+                let mut exp = hist.clone();
+                for hist in [&mut exp, &mut others] {
+                    hist.smooth_with(&config.smoothing);
+                    hist.normalize();
+                }
+
+                return config
+                    .metrics
+                    .iter()
+                    .map(|spec| {
+                        let score = score_rows(
+                            config,
+                            spec,
+                            slice::from_ref(&exp.counts),
+                            &[factor],
+                            &others.counts,
+                        )?;
+                        Ok((spec.column_name(feat), score[0]))
+                    })
+                    .collect();
+            })
+            .collect::<Result<_, HistDiffError>>()?;
+
+        scores.insert(well.clone(), per_feature.into_iter().flatten().collect());
+    }
+
+    return Ok(scores);
+}
+
+/// HistDiff factor of a vehicle well with `cells` cells scored against the `others` cells
+/// of the other `other_vehicles` vehicles of its block
+fn leave_one_out_factor(
+    config: &UserConfig,
+    cells: usize,
+    others: usize,
+    other_vehicles: usize,
+) -> f64 {
+    return match config.factor {
+        ScoreFactor::Constant(factor) => factor,
+        ScoreFactor::CellCountRatio => cell_count_ratio(cells, others, other_vehicles),
+    };
+}
+
+/// Cell count of a well over the mean cell count of the `vehicles` wells pooling `pooled` cells
+fn cell_count_ratio(cells: usize, pooled: usize, vehicles: usize) -> f64 {
+    return cells as f64 * vehicles as f64 / pooled as f64;
+}
+
+/// Splits the vehicles of a block into the ones kept in the control pool and the outliers
+///
+/// A vehicle is rejected when the median absolute leave-one-out score over the non-constant
+/// features, under the first metric of `config.metrics`, is above `threshold`.
+///
+/// # returns:
+/// - (kept vehicles, rejected vehicles); nothing is rejected with fewer than 2 vehicles
+fn reject_vehicles(
+    config: &UserConfig,
+    vehicles: Vec<String>,
+    histograms: &WellHistograms,
+    cell_counts: &HashMap<String, usize>,
+    features: &[String],
+    constant: &HashSet<&str>,
+    threshold: f64,
+) -> Result<(Vec<String>, Vec<RejectedVehicle>), HistDiffError> {
+    let Some(spec) = config.metrics.first() else {
+        return Ok((vehicles, Vec::new()));
+    };
+    let loo = leave_one_out_scores(
+        config,
+        &vehicles,
+        histograms,
+        cell_counts,
+        features,
+        constant,
+    )?;
+
+    let mut kept: Vec<String> = Vec::new();
+    let mut rejected: Vec<RejectedVehicle> = Vec::new();
+    for well in vehicles {
+        let scores: Vec<f64> = features
+            .iter()
+            .filter(|feat| !constant.contains(feat.as_str()))
+            .filter_map(|feat| loo.get(&well)?.get(&spec.column_name(feat)))
+            .map(|score| score.abs())
+            .collect();
+
+        match median(&scores) {
+            Some(score) if score > threshold => rejected.push(RejectedVehicle { well, score }),
+            _ => kept.push(well),
+        }
+    }
+
+    if config.verbose && !rejected.is_empty() {
+        warn!("Vehicle controls left out of the pool: {:?}", rejected);
+    }
+
+    return Ok((kept, rejected));
+}
+
+/// Robust z-scores of every score against the leave-one-out vehicle scores of its block
+///
+/// # params:
+/// - blocks => (wells with data, vehicles with data) of every block
+fn z_scores(
+    hd_scores: &WellScores,
+    vehicle_null: &WellScores,
+    blocks: &[(Vec<String>, Vec<String>)],
+) -> WellScores {
+    let mut z_scores: WellScores = HashMap::new();
+    for (wells, vehicles) in blocks {
+        let mut columns: Vec<&String> = wells
+            .iter()
+            .filter_map(|well| hd_scores.get(well))
+            .flat_map(|scores| scores.keys())
+            .collect();
+        columns.sort();
+        columns.dedup();
+
+        for column in columns {
+            let null: Vec<f64> = vehicles
+                .iter()
+                .filter_map(|well| vehicle_null.get(well)?.get(column).copied())
+                .collect();
+            let (scored, scores): (Vec<&String>, Vec<f64>) = wells
+                .iter()
+                .filter_map(|well| Some((well, *hd_scores.get(well)?.get(column)?)))
+                .unzip();
+
+            for (well, z) in scored.into_iter().zip(robust_z_scores(&scores, &null)) {
+                z_scores
+                    .entry(well.clone())
+                    .or_default()
+                    .insert(column.clone(), z);
+            }
+        }
+    }
+
+    return z_scores;
 }
@@ -6,8 +6,28 @@ use std::{
     path::Path,
 };
 
+use crate::hd_core::{
+    calculations::MinMaxPlateResult,
+    error::HistDiffError,
+    histograms::HistCounts,
+    pooling::RejectedVehicle,
+    problems::ProblematicFeature,
+    selection::ExcludedColumn,
+    utils::{ScoreFactor, UserConfig},
+};
+
+mod batch;
 mod histdiff;
-pub use histdiff::calculate_scores;
+mod single_pass;
+pub use batch::{calculate_scores_batch, plates_from_glob, BatchOptions, RangeScope};
+pub use histdiff::{calculate_scores, calculate_scores_df, calculate_scores_lazy};
+
+/// well => feature or score column => value
+pub(crate) type WellScores = HashMap<String, HashMap<String, f64>>;
+/// well => feature => entry/underflow/overflow/NaN counts
+pub(crate) type WellCounts = HashMap<String, HashMap<String, HistCounts>>;
+/// Reads one QC count out of a histogram's counts
+type CountOf = fn(&HistCounts) -> u64;
 
 /// Stores the HistDiff calculation
 ///
@@ -16,35 +36,415 @@ pub use histdiff::calculate_scores;
 pub struct HistDiffRes {
     pub raw_scores: HashMap<String, HashMap<String, f64>>,
     pub dataframe_scores: Option<DataFrame>,
+    /// vehicle control wells that never appeared in the data
+    pub unseen_vehicles: Vec<String>,
+    /// wells of the plate or block definitions that never appeared in the data
+    pub unseen_wells: Vec<String>,
+    /// HistDiff scaling factor used for every well (see `ScoreFactor`)
+    pub factors: HashMap<String, f64>,
+    /// well => feature => entry/underflow/overflow/NaN counts of its histogram
+    pub qc: HashMap<String, HashMap<String, HistCounts>>,
+    /// feature => histogram bin count (see `BinOptions`), `"{plate}:{feature}"` in multi-plate runs
+    pub nbins: HashMap<String, usize>,
+    /// non-ID columns that were not scored and why, `"{plate}:{column}"` in multi-plate runs
+    pub excluded_columns: Vec<ExcludedColumn>,
+    /// all-NaN, sparse and constant features and what was done with them (see `ProblemPolicy`),
+    /// `"{plate}:{feature}"` in multi-plate runs
+    pub problematic_features: Vec<ProblematicFeature>,
+    /// well => number of cell rows read for it
+    pub cell_counts: HashMap<String, usize>,
+    /// well => pooled cell count of the vehicle controls of its block
+    pub vehicle_cells: HashMap<String, usize>,
+    /// wells with fewer cells than `CellCountOptions.min_cells`, in plate order
+    pub low_cell_wells: Vec<String>,
+    /// well => score column => resampling p-value, empty unless `UserConfig.significance` is set
+    pub p_values: HashMap<String, HashMap<String, f64>>,
+    /// well => score column => Benjamini–Hochberg q-value over the wells of the plate
+    pub q_values: HashMap<String, HashMap<String, f64>>,
+    /// vehicle well => score column => its score against the other vehicles of its block,
+    /// empty unless `UserConfig.standardize` is set
+    pub vehicle_null: HashMap<String, HashMap<String, f64>>,
+    /// well => score column => robust z-score against the `vehicle_null` scores of its block
+    pub z_scores: HashMap<String, HashMap<String, f64>>,
+    /// `z_scores` laid out like `dataframe_scores`, `None` unless `UserConfig.standardize` is set
+    pub dataframe_z_scores: Option<DataFrame>,
+    /// vehicle wells left out of their block's control pool (see `PoolingOptions`),
+    /// `"{plate}:{well}"` in multi-plate runs
+    pub rejected_vehicles: Vec<RejectedVehicle>,
 }
 
 impl HistDiffRes {
     /// Creates a formal output for the HistDiff scores
-    pub fn new(scores: HashMap<String, HashMap<String, f64>>) -> Self {
-        let df = to_df(&scores).expect("Can't convert to dataframe");
-        Self {
+    pub fn new(scores: HashMap<String, HashMap<String, f64>>) -> Result<Self, HistDiffError> {
+        let df = to_df(&scores)?;
+        Ok(Self {
             raw_scores: scores,
             dataframe_scores: Some(df),
+            unseen_vehicles: Vec::new(),
+            unseen_wells: Vec::new(),
+            factors: HashMap::new(),
+            qc: HashMap::new(),
+            nbins: HashMap::new(),
+            excluded_columns: Vec::new(),
+            problematic_features: Vec::new(),
+            cell_counts: HashMap::new(),
+            vehicle_cells: HashMap::new(),
+            low_cell_wells: Vec::new(),
+            p_values: HashMap::new(),
+            q_values: HashMap::new(),
+            vehicle_null: HashMap::new(),
+            z_scores: HashMap::new(),
+            dataframe_z_scores: None,
+            rejected_vehicles: Vec::new(),
+        })
+    }
+
+    /// Creates the output of a single plate run
+    ///
+    /// A `factor` column is added unless the default constant factor of 1.0 was used,
+    /// QC columns when `config.qc_columns` is set.
+    pub(crate) fn from_plate(
+        plate: PlateScores,
+        config: &UserConfig,
+    ) -> Result<Self, HistDiffError> {
+        let mut res = HistDiffRes::new(plate.scores)?;
+        res.unseen_vehicles = plate.unseen_vehicles;
+        res.unseen_wells = plate.unseen_wells;
+        res.factors = plate.factors;
+        res.qc = plate.qc;
+        res.nbins = plate.nbins;
+        res.excluded_columns = plate.excluded_columns;
+        res.problematic_features = plate.problematic_features;
+        res.cell_counts = plate.cell_counts;
+        res.vehicle_cells = plate.vehicle_cells;
+        res.low_cell_wells = plate.low_cell_wells;
+        res.p_values = plate.p_values;
+        res.q_values = plate.q_values;
+        res.vehicle_null = plate.vehicle_null;
+        res.z_scores = plate.z_scores;
+        res.rejected_vehicles = plate.rejected_vehicles;
+        if config.standardize {
+            res.dataframe_z_scores = Some(to_df(&res.z_scores)?);
+        }
+        res.add_columns(config)?;
+
+        Ok(res)
+    }
+
+    /// Creates the combined output of a multi-plate run
+    ///
+    /// Rows are keyed `"{plate}:{well}"`; the dataframe also gets `plate` and `well` columns.
+    /// Unseen wells are keyed the same way.
+    pub(crate) fn from_plates(
+        plates: Vec<(String, PlateScores)>,
+        config: &UserConfig,
+    ) -> Result<Self, HistDiffError> {
+        let mut scores: WellScores = HashMap::new();
+        let mut unseen_vehicles: Vec<String> = Vec::new();
+        let mut unseen_wells: Vec<String> = Vec::new();
+        let mut factors: HashMap<String, f64> = HashMap::new();
+        let mut qc: WellCounts = HashMap::new();
+        let mut nbins: HashMap<String, usize> = HashMap::new();
+        let mut excluded_columns: Vec<ExcludedColumn> = Vec::new();
+        let mut problematic_features: Vec<ProblematicFeature> = Vec::new();
+        let mut cell_counts: HashMap<String, usize> = HashMap::new();
+        let mut vehicle_cells: HashMap<String, usize> = HashMap::new();
+        let mut low_cell_wells: Vec<String> = Vec::new();
+        let mut p_values: WellScores = HashMap::new();
+        let mut q_values: WellScores = HashMap::new();
+        let mut vehicle_null: WellScores = HashMap::new();
+        let mut z_scores: WellScores = HashMap::new();
+        let mut rejected_vehicles: Vec<RejectedVehicle> = Vec::new();
+        for (plate, plate_scores) in plates {
+            for (well, feats) in plate_scores.scores {
+                scores.insert(format!("{}:{}", plate, well), feats);
+            }
+            let keyed = |well: String| format!("{}:{}", plate, well);
+            unseen_vehicles.extend(plate_scores.unseen_vehicles.into_iter().map(keyed));
+            unseen_wells.extend(plate_scores.unseen_wells.into_iter().map(keyed));
+            factors.extend(
+                plate_scores
+                    .factors
+                    .into_iter()
+                    .map(|(well, factor)| (keyed(well), factor)),
+            );
+            qc.extend(
+                plate_scores
+                    .qc
+                    .into_iter()
+                    .map(|(well, counts)| (keyed(well), counts)),
+            );
+            nbins.extend(
+                plate_scores
+                    .nbins
+                    .into_iter()
+                    .map(|(feat, bins)| (keyed(feat), bins)),
+            );
+            excluded_columns.extend(plate_scores.excluded_columns.into_iter().map(|c| {
+                ExcludedColumn {
+                    column: keyed(c.column),
+                    reason: c.reason,
+                }
+            }));
+            problematic_features.extend(plate_scores.problematic_features.into_iter().map(|p| {
+                ProblematicFeature {
+                    feature: keyed(p.feature),
+                    ..p
+                }
+            }));
+            cell_counts.extend(
+                plate_scores
+                    .cell_counts
+                    .into_iter()
+                    .map(|(well, cells)| (keyed(well), cells)),
+            );
+            vehicle_cells.extend(
+                plate_scores
+                    .vehicle_cells
+                    .into_iter()
+                    .map(|(well, cells)| (keyed(well), cells)),
+            );
+            low_cell_wells.extend(plate_scores.low_cell_wells.into_iter().map(keyed));
+            p_values.extend(
+                plate_scores
+                    .p_values
+                    .into_iter()
+                    .map(|(well, p)| (keyed(well), p)),
+            );
+            q_values.extend(
+                plate_scores
+                    .q_values
+                    .into_iter()
+                    .map(|(well, q)| (keyed(well), q)),
+            );
+            vehicle_null.extend(
+                plate_scores
+                    .vehicle_null
+                    .into_iter()
+                    .map(|(well, null)| (keyed(well), null)),
+            );
+            z_scores.extend(
+                plate_scores
+                    .z_scores
+                    .into_iter()
+                    .map(|(well, z)| (keyed(well), z)),
+            );
+            rejected_vehicles.extend(plate_scores.rejected_vehicles.into_iter().map(|r| {
+                RejectedVehicle {
+                    well: keyed(r.well),
+                    ..r
+                }
+            }));
+        }
+
+        let mut res = HistDiffRes::new(scores)?;
+        res.unseen_vehicles = unseen_vehicles;
+        res.unseen_wells = unseen_wells;
+        res.factors = factors;
+        res.qc = qc;
+        res.nbins = nbins;
+        res.excluded_columns = excluded_columns;
+        res.problematic_features = problematic_features;
+        res.cell_counts = cell_counts;
+        res.vehicle_cells = vehicle_cells;
+        res.low_cell_wells = low_cell_wells;
+        res.p_values = p_values;
+        res.q_values = q_values;
+        res.vehicle_null = vehicle_null;
+        res.z_scores = z_scores;
+        res.rejected_vehicles = rejected_vehicles;
+        if config.standardize {
+            res.dataframe_z_scores = Some(to_df(&res.z_scores)?);
+        }
+        for df in [&mut res.dataframe_scores, &mut res.dataframe_z_scores]
+            .into_iter()
+            .flatten()
+        {
+            let (plates, wells): (Vec<String>, Vec<String>) = df
+                .column("id")?
+                .str()?
+                .iter()
+                .map(|id| {
+                    let (plate, well) = id.and_then(|id| id.rsplit_once(':')).unwrap_or(("", ""));
+                    (plate.to_string(), well.to_string())
+                })
+                .unzip();
+
+            df.insert_column(1, Column::new("plate".into(), plates))?;
+            df.insert_column(2, Column::new("well".into(), wells))?;
+        }
+        res.add_columns(config)?;
+
+        Ok(res)
+    }
+
+    /// Adds the optional per-well columns the config asks for
+    ///
+    /// - `factor` after the id columns when the factor is not the default
+    /// - `cells` and `vehicle_cells` after those when `config.cells.columns` is set,
+    ///   and `low_cells` when `config.cells.min_cells` is set
+    /// - `{feature}_entries`, `_underflow`, `_overflow` and `_nan` at the end when `config.qc_columns` is set
+    /// - `{column}_p` and `{column}_q` for every score column at the end when `config.significance` is set
+    fn add_columns(&mut self, config: &UserConfig) -> Result<(), HistDiffError> {
+        let Some(df) = &mut self.dataframe_scores else {
+            return Ok(());
+        };
+
+        // inserted right after the id columns, so the last one inserted comes first
+        if config.cells.min_cells > 0 {
+            let low: HashMap<String, bool> = self
+                .cell_counts
+                .keys()
+                .map(|well| (well.clone(), self.low_cell_wells.contains(well)))
+                .collect();
+            insert_id_column(df, "low_cells", &low)?;
+        }
+        if config.cells.columns {
+            let as_u64 = |counts: &HashMap<String, usize>| -> HashMap<String, u64> {
+                counts
+                    .iter()
+                    .map(|(well, &cells)| (well.clone(), cells as u64))
+                    .collect()
+            };
+            insert_id_column(df, "vehicle_cells", &as_u64(&self.vehicle_cells))?;
+            insert_id_column(df, "cells", &as_u64(&self.cell_counts))?;
         }
+        if config.factor != ScoreFactor::default() {
+            insert_id_column(df, "factor", &self.factors)?;
+        }
+
+        if config.qc_columns {
+            let ids: Vec<Option<String>> = df
+                .column("id")?
+                .str()?
+                .iter()
+                .map(|id| id.map(|id| id.to_string()))
+                .collect();
+
+            let mut features: Vec<&String> = self.qc.values().flat_map(|f| f.keys()).collect();
+            features.sort();
+            features.dedup();
+
+            let kinds: [(&str, CountOf); 4] = [
+                ("entries", |c| c.entries),
+                ("underflow", |c| c.underflow),
+                ("overflow", |c| c.overflow),
+                ("nan", |c| c.nan),
+            ];
+            for feat in features {
+                for (kind, count) in kinds {
+                    let column: Vec<Option<u64>> = ids
+                        .iter()
+                        .map(|id| {
+                            let counts = self.qc.get(id.as_ref()?)?.get(feat)?;
+                            Some(count(counts))
+                        })
+                        .collect();
+                    df.with_column(Column::new(format!("{}_{}", feat, kind).into(), column))?;
+                }
+            }
+        }
+
+        if config.significance.is_some() {
+            let ids: Vec<Option<String>> = df
+                .column("id")?
+                .str()?
+                .iter()
+                .map(|id| id.map(|id| id.to_string()))
+                .collect();
+
+            let mut columns: Vec<&String> = self.p_values.values().flat_map(|p| p.keys()).collect();
+            columns.sort();
+            columns.dedup();
+
+            for column in columns {
+                for (suffix, values) in [("p", &self.p_values), ("q", &self.q_values)] {
+                    let values: Vec<Option<f64>> = ids
+                        .iter()
+                        .map(|id| values.get(id.as_ref()?)?.get(column).copied())
+                        .collect();
+                    df.with_column(Column::new(format!("{}_{}", column, suffix).into(), values))?;
+                }
+            }
+        }
+
+        Ok(())
     }
 
     /// Given an output path, output the scores as a csv file
     /// *Note: file must end in a .csv extension*
-    pub fn to_csv<P: AsRef<Path>>(&mut self, path: P) -> &Self {
-        let mut file = File::create(path).expect("Could not create file!");
+    pub fn to_csv<P: AsRef<Path>>(&mut self, path: P) -> Result<&Self, HistDiffError> {
+        let mut file = File::create(path)?;
         if let Some(df) = &mut self.dataframe_scores {
-            _ = CsvWriter::new(&mut file)
+            CsvWriter::new(&mut file)
                 .include_header(true)
                 .with_separator(b',')
-                .finish(df);
+                .finish(df)?;
         }
 
-        self
+        Ok(self)
     }
 }
 
+/// Scores of one plate, before they are turned into a `HistDiffRes`
+pub(crate) struct PlateScores {
+    pub scores: WellScores,
+    pub factors: HashMap<String, f64>,
+    pub qc: WellCounts,
+    pub nbins: HashMap<String, usize>,
+    pub cell_counts: HashMap<String, usize>,
+    pub vehicle_cells: HashMap<String, usize>,
+    pub low_cell_wells: Vec<String>,
+    pub p_values: WellScores,
+    pub q_values: WellScores,
+    pub vehicle_null: WellScores,
+    pub z_scores: WellScores,
+    pub rejected_vehicles: Vec<RejectedVehicle>,
+    pub excluded_columns: Vec<ExcludedColumn>,
+    pub problematic_features: Vec<ProblematicFeature>,
+    pub unseen_vehicles: Vec<String>,
+    pub unseen_wells: Vec<String>,
+}
+
+impl PlateScores {
+    /// Attaches the column and feature reports of the ranges the plate was scored with
+    pub fn with_report(mut self, min_max: &MinMaxPlateResult) -> Self {
+        self.excluded_columns = min_max.excluded_columns.clone();
+        self.problematic_features = min_max.problematic_features.clone();
+        return self;
+    }
+}
+
+/// Inserts a per-row column looked up by the `id` column, after the id/plate/well columns
+fn insert_id_column<T>(
+    df: &mut DataFrame,
+    name: &str,
+    values: &HashMap<String, T>,
+) -> Result<(), HistDiffError>
+where
+    T: Copy,
+    Series: NamedFrom<Vec<Option<T>>, [Option<T>]>,
+{
+    let column: Vec<Option<T>> = df
+        .column("id")?
+        .str()?
+        .iter()
+        .map(|id| id.and_then(|id| values.get(id).copied()))
+        .collect();
+
+    let position = ["id", "plate", "well"]
+        .iter()
+        .filter_map(|c| df.get_column_index(c))
+        .max()
+        .unwrap_or(0)
+        + 1;
+    df.insert_column(position, Column::new(name.into(), column))?;
+
+    Ok(())
+}
+
 /// convert the raw scores into a polars dataframe
-fn to_df(raw_out: &HashMap<String, HashMap<String, f64>>) -> Result<DataFrame, PolarsError> {
+fn to_df(raw_out: &WellScores) -> Result<DataFrame, PolarsError> {
     let mut row_keys: Vec<&String> = raw_out.keys().collect();
     row_keys.sort();
 
@@ -77,5 +477,5 @@ fn to_df(raw_out: &HashMap<String, HashMap<String, f64>>) -> Result<DataFrame, P
         series_list.push(series.into());
     }
 
-    return DataFrame::new(series_list);
+    return DataFrame::new_infer_height(series_list);
 }
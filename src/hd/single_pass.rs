@@ -0,0 +1,141 @@
+use core::f64;
+use log::info;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
+    IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    hd_core::{
+        calculations::{bin_rows, range_rows, MinMax, MinMaxPlateResult, RawExtrema},
+        error::HistDiffError,
+        reader::CellSource,
+        utils::well_set,
+    },
+    Hist1D, UserConfig,
+};
+
+use super::histdiff::{by_well, count_cells, PlateHistograms};
+
+/// Reads the cell data once and returns both the plate ranges and the filled histograms
+///
+/// Every well's values are buffered as one `f32` column per feature until the final
+/// `xlow`/`xhigh` are known, then binned exactly like the two-pass reader.
+/// See `ReadMode::SinglePass` for the memory cost and score tolerance.
+pub(crate) fn read_single_pass(
+    config: &UserConfig,
+    source: &CellSource,
+) -> Result<(MinMaxPlateResult, PlateHistograms), HistDiffError> {
+    config.range.validate()?;
+    config.binning.validate()?;
+
+    let mut reader = source.open(config)?;
+    let feats: Vec<String> = reader.features.clone();
+
+    let plate_def: HashSet<String> = well_set(&config.plate_def);
+    let vehicles: HashSet<String> = well_set(&config.vehicle_cntrls);
+
+    // running extrema over every row, same as `get_min_max_plate`
+    let mut extrema = RawExtrema::new(feats.clone(), config);
+    extrema.excluded = reader.excluded.clone();
+
+    // well => buffered values, one map per feature (indexed like `feats`)
+    let mut buffers: Vec<HashMap<String, Vec<f32>>> = vec![HashMap::new(); feats.len()];
+    let mut cell_counts: HashMap<String, usize> = HashMap::new();
+
+    let start_t = std::time::Instant::now();
+    if config.verbose {
+        info!("Begin single pass read of cell data file.");
+    }
+
+    while let Some(batch) = reader.next_batch()? {
+        let rows = range_rows(config, &vehicles, &batch.wells);
+        let bin_rows = bin_rows(config, &vehicles, &batch.wells);
+        extrema.update(&batch.columns, rows.as_deref(), bin_rows.as_deref());
+        count_cells(&mut cell_counts, &batch.wells, &plate_def);
+
+        buffers
+            .par_iter_mut()
+            .zip(batch.columns.par_iter())
+            .for_each(|(wells, column)| {
+                for (well, &value) in batch.wells.iter().zip(column) {
+                    if !plate_def.contains(well.as_str()) {
+                        continue;
+                    }
+
+                    match wells.get_mut(well) {
+                        Some(values) => values.push(value as f32),
+                        None => {
+                            wells.insert(well.clone(), vec![value as f32]);
+                        }
+                    }
+                }
+            });
+    }
+
+    if config.verbose {
+        info!("Time to read file: {:?}", start_t.elapsed());
+    }
+
+    let min_max = extrema.finalize(config)?;
+
+    let start_t = std::time::Instant::now();
+    if config.verbose {
+        info!("Binning buffered values into histograms.");
+    }
+
+    // only keep the buffers of features that survived the min max step
+    let mut buffers: HashMap<String, HashMap<String, Vec<f32>>> =
+        feats.into_iter().zip(buffers).collect();
+    let kept: Vec<HashMap<String, Vec<f32>>> = min_max
+        .min_max
+        .iter()
+        .map(|(feat, _)| buffers.remove(feat).unwrap_or_default())
+        .collect();
+
+    let templates: Vec<Hist1D> = min_max.empty_histograms()?;
+    let per_feature: Vec<HashMap<String, Hist1D>> = kept
+        .into_par_iter()
+        .zip(min_max.min_max.par_iter())
+        .zip(templates.par_iter())
+        .map(|((wells, (_, range)), template)| {
+            wells
+                .into_iter()
+                .map(|(well, values)| {
+                    let values: Vec<f64> = values
+                        .into_iter()
+                        .map(|v| undo_f32_rounding(v as f64, range))
+                        .collect();
+
+                    let mut hist = template.clone();
+                    hist.fill_with(&values, config.range.out_of_range);
+                    (well, hist)
+                })
+                .collect()
+        })
+        .collect();
+
+    if config.verbose {
+        info!("Time to bin buffered values: {:?}", start_t.elapsed());
+    }
+
+    let plate = PlateHistograms {
+        histograms: by_well(&min_max.features, per_feature),
+        cell_counts,
+    };
+
+    return Ok((min_max, plate));
+}
+
+/// Pulls a value back into range when only `f32` rounding pushed it past an edge
+fn undo_f32_rounding(value: f64, range: &MinMax) -> f64 {
+    let tolerance = |edge: f64| edge.abs() * f32::EPSILON as f64;
+    if value < range.xlow && range.xlow - value <= tolerance(range.xlow) {
+        return range.xlow;
+    }
+    if value > range.xhigh && value - range.xhigh <= tolerance(range.xhigh) {
+        return range.xhigh;
+    }
+    return value;
+}
@@ -0,0 +1,151 @@
+use super::{
+    calculations::RangeCells,
+    error::HistDiffError,
+    sketch::{QuantileSketch, SKETCH_K},
+};
+
+/// How many bins the histogram of every feature gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinRule {
+    /// `UserConfig.nbins` for every feature
+    #[default]
+    Fixed,
+    /// `ceil(log2(n)) + 1`
+    Sturges,
+    /// bin width `3.49 * std * n^(-1/3)`
+    Scott,
+    /// bin width `2 * IQR * n^(-1/3)`
+    FreedmanDiaconis,
+    /// `ceil(sqrt(n))`
+    Sqrt,
+}
+
+/// Bin rule, the cells it is computed from and an upper bound on the result
+///
+/// Width based rules (Scott, Freedman-Diaconis) divide the final histogram range of
+/// the feature by the rule's bin width; when the spread of the cells is 0 they fall
+/// back to Sturges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinOptions {
+    pub rule: BinRule,
+    pub cells: RangeCells,
+    pub max_bins: usize,
+}
+
+impl Default for BinOptions {
+    fn default() -> Self {
+        BinOptions {
+            rule: BinRule::Fixed,
+            cells: RangeCells::All,
+            max_bins: 512,
+        }
+    }
+}
+
+impl BinOptions {
+    /// Checks the bin count bound
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        if self.max_bins == 0 {
+            return Err(HistDiffError::InvalidConfig(
+                "max_bins must be at least 1".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+/// Running per-feature statistics the bin rules need
+#[derive(Debug, Clone)]
+pub(crate) struct BinStats {
+    n: u64,
+    mean: f64,
+    m2: f64,
+    /// only kept for Freedman-Diaconis
+    sketch: Option<QuantileSketch>,
+}
+
+impl BinStats {
+    pub fn new(rule: BinRule) -> Self {
+        BinStats {
+            n: 0,
+            mean: 0.0,
+            m2: 0.0,
+            sketch: match rule {
+                BinRule::FreedmanDiaconis => Some(QuantileSketch::new(SKETCH_K)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Adds a value (Welford's update); non-finite values are ignored
+    pub fn insert(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.n += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (value - self.mean);
+
+        if let Some(sketch) = &mut self.sketch {
+            sketch.insert(value);
+        }
+    }
+
+    /// Folds another set of statistics into this one
+    pub fn merge(&mut self, other: &BinStats) {
+        if other.n > 0 {
+            let n = self.n + other.n;
+            let delta = other.mean - self.mean;
+            self.m2 += other.m2 + delta * delta * (self.n as f64 * other.n as f64) / n as f64;
+            self.mean += delta * other.n as f64 / n as f64;
+            self.n = n;
+        }
+
+        if let (Some(sketch), Some(other)) = (&mut self.sketch, &other.sketch) {
+            sketch.merge(other);
+        }
+    }
+
+    /// Bin count `options.rule` picks for a histogram `width` wide, `None` without data
+    pub fn bins(&self, options: &BinOptions, fixed: usize, width: f64) -> Option<usize> {
+        if self.n == 0 {
+            return None;
+        }
+
+        let n = self.n as f64;
+        let sturges = n.log2().ceil() + 1.0;
+        let from_width = |bin_width: f64| -> f64 {
+            if bin_width > 0.0 && bin_width.is_finite() && width.is_finite() {
+                (width / bin_width).ceil()
+            } else {
+                sturges
+            }
+        };
+
+        let bins = match options.rule {
+            BinRule::Fixed => fixed as f64,
+            BinRule::Sturges => sturges,
+            BinRule::Sqrt => n.sqrt().ceil(),
+            BinRule::Scott => {
+                let std = if self.n > 1 {
+                    (self.m2 / (n - 1.0)).sqrt()
+                } else {
+                    0.0
+                };
+                from_width(3.49 * std * n.powf(-1.0 / 3.0))
+            }
+            BinRule::FreedmanDiaconis => {
+                let iqr = self
+                    .sketch
+                    .as_ref()
+                    .and_then(|s| Some(s.quantile(0.75)? - s.quantile(0.25)?))
+                    .unwrap_or(0.0);
+                from_width(2.0 * iqr * n.powf(-1.0 / 3.0))
+            }
+        };
+
+        return Some((bins as usize).clamp(1, options.max_bins));
+    }
+}
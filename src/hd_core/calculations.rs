@@ -3,14 +3,24 @@
 use core::f64;
 use dashmap::DashMap;
 use log::{info, trace};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::collections::{HashMap, HashSet};
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
-use std::usize;
-
-use super::utils::UserConfig;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use super::binning::{BinRule, BinStats};
+use super::error::HistDiffError;
+use super::histograms::{Hist1D, OutOfRange};
+use super::problems::{ProblemAction, ProblematicFeature};
+use super::reader::CellSource;
+use super::selection::ExcludedColumn;
+use super::sketch::{QuantileSketch, SKETCH_K};
+use super::utils::{well_set, UserConfig};
 
 /// exponential smoothing function
 pub fn exponential_smoothing(x: &[f64], alpha: f64) -> Vec<f64> {
@@ -37,6 +47,111 @@ pub fn exponential_smoothing(x: &[f64], alpha: f64) -> Vec<f64> {
     return smoothing;
 }
 
+/// gaussian kernel smoothing
+///
+/// `sigma` is in bins; the kernel is cut at 3 sigma, or at the width of the histogram,
+/// and renormalized where it is truncated by the edges of the histogram.
+pub fn gaussian_smoothing(x: &[f64], sigma: f64) -> Vec<f64> {
+    // wider kernels only add weights that never meet a bin
+    let radius = ((3.0 * sigma).ceil() as usize).min(x.len());
+    let kernel: Vec<f64> = (0..=radius)
+        .map(|d| (-0.5 * (d as f64 / sigma).powi(2)).exp())
+        .collect();
+
+    return kernel_smoothing(x, radius, |d| kernel[d]);
+}
+
+/// centered moving average over a window of `k` bins (rounded up to odd), truncated at the edges
+pub fn moving_average(x: &[f64], k: usize) -> Vec<f64> {
+    return kernel_smoothing(x, k / 2, |_| 1.0);
+}
+
+/// weighted average of the neighbours within `radius`, `weight(distance)` gives the kernel
+fn kernel_smoothing(x: &[f64], radius: usize, weight: impl Fn(usize) -> f64) -> Vec<f64> {
+    let n = x.len();
+    return (0..n)
+        .map(|i| {
+            let start = i.saturating_sub(radius);
+            let (mut sum, mut norm) = (0.0, 0.0);
+            for (j, value) in x[start..(i + radius + 1).min(n)].iter().enumerate() {
+                let w = weight(i.abs_diff(start + j));
+                sum += w * value;
+                norm += w;
+            }
+            sum / norm
+        })
+        .collect();
+}
+
+/// Savitzky-Golay smoothing
+///
+/// Fits a polynomial of degree `order` to the `window` bins around every bin by
+/// least squares and takes its value at that bin. Windows are shifted inwards at
+/// the edges instead of padded. Negative fitted values are clipped to 0.
+pub fn savitzky_golay(x: &[f64], window: usize, order: usize) -> Vec<f64> {
+    let n = x.len();
+    let window = window.min(n);
+    let order = order.min(window.saturating_sub(1));
+    let half = window / 2;
+
+    return (0..n)
+        .map(|i| {
+            let start = i.saturating_sub(half).min(n - window);
+            let points: Vec<(f64, f64)> = (start..start + window)
+                .map(|j| (j as f64 - i as f64, x[j]))
+                .collect();
+            polyfit_at_zero(&points, order).max(0.0)
+        })
+        .collect();
+}
+
+/// value at 0 of the least squares polynomial of degree `order` through `points`
+fn polyfit_at_zero(points: &[(f64, f64)], order: usize) -> f64 {
+    let m = order + 1;
+
+    // normal equations A c = b with A[r][c] = sum x^(r+c), b[r] = sum x^r y
+    let mut a: Vec<Vec<f64>> = (0..m)
+        .map(|r| {
+            let mut row: Vec<f64> = (0..m)
+                .map(|c| points.iter().map(|(x, _)| x.powi((r + c) as i32)).sum())
+                .collect();
+            row.push(points.iter().map(|(x, y)| x.powi(r as i32) * y).sum());
+            row
+        })
+        .collect();
+
+    // gaussian elimination with partial pivoting
+    for col in 0..m {
+        let pivot = (col..m)
+            .max_by(|&p, &q| a[p][col].abs().total_cmp(&a[q][col].abs()))
+            .unwrap_or(col);
+        a.swap(col, pivot);
+        if a[col][col] == 0.0 {
+            continue;
+        }
+        let (upper, lower) = a.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for row in lower {
+            let ratio = row[col] / pivot_row[col];
+            for (value, pivot) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= ratio * pivot;
+            }
+        }
+    }
+
+    let mut coefs = vec![0.0; m];
+    for row in (0..m).rev() {
+        if a[row][row] == 0.0 {
+            continue;
+        }
+        let tail: f64 = ((row + 1)..m).map(|c| a[row][c] * coefs[c]).sum();
+        coefs[row] = (a[row][m] - tail) / a[row][row];
+    }
+
+    // the polynomial is centered on the bin so its value there is the constant term
+    return coefs[0];
+}
+
 /// normalization function
 pub fn normalize(x: &[f64]) -> Vec<f64> {
     let sum: f64 = x.iter().sum();
@@ -48,116 +163,532 @@ pub fn normalize(x: &[f64]) -> Vec<f64> {
 }
 
 /// Holds the min max value results
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MinMax {
     pub xlow: f64,
     pub xhigh: f64,
 }
 
 /// Holds the min max values for the entire dataset
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MinMaxPlateResult {
     pub min_max: Vec<(String, MinMax)>,
     pub features: Vec<String>,
-    pub problemativ_features: Option<Vec<String>>,
+    /// all-NaN, sparse and constant features, dropped or kept according to `UserConfig.problems`
+    pub problematic_features: Vec<ProblematicFeature>,
+    /// bin count of every feature, indexed like `min_max`
+    pub nbins: Vec<usize>,
+    /// non-ID columns that were not read as features (see `FeatureSelection`)
+    pub excluded_columns: Vec<ExcludedColumn>,
+    /// kept features with a single value in every cell of the plate, they score 0
+    pub constant_features: Vec<String>,
 }
 
-/// retrieves the min max values for a given dataset
-pub fn get_min_max_plate(config: &UserConfig) -> Result<MinMaxPlateResult, Box<dyn Error>> {
-    if config.verbose {
-        info!("Starting Min Max Process for all specified features.");
+impl MinMaxPlateResult {
+    /// An empty histogram per feature, indexed like `min_max`
+    pub fn empty_histograms(&self) -> Result<Vec<Hist1D>, HistDiffError> {
+        return self
+            .min_max
+            .iter()
+            .zip(&self.nbins)
+            .map(|((_, range), &nbins)| Hist1D::new(nbins, range.xlow, range.xhigh))
+            .collect();
     }
 
-    let file = File::open(config.path.clone())?;
-    let reader = BufReader::new(file);
+    /// Bin count chosen for `feature`, if it was kept
+    pub fn nbins_of(&self, feature: &str) -> Option<usize> {
+        return self
+            .min_max
+            .iter()
+            .position(|(feat, _)| feat == feature)
+            .map(|j| self.nbins[j]);
+    }
+}
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .flexible(true)
-        .has_headers(true)
-        .from_reader(reader);
+/// How the histogram range of every feature is picked
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum RangeStrategy {
+    /// Minimum and maximum of the finite values
+    #[default]
+    MinMax,
+    /// Clips the range to the given percentiles (0-100), e.g. 0.1 and 99.9
+    ///
+    /// Percentiles come from a streaming quantile sketch, so they are exact for
+    /// small inputs and within ~0.2% rank for large ones.
+    Percentile { low: f64, high: f64 },
+    /// Fixed per-feature ranges; features without an entry fall back to min/max
+    ///
+    /// Ranges of transformed features are on the transformed scale.
+    Fixed(HashMap<String, MinMax>),
+}
 
-    let headers = csv_reader.headers()?.clone();
-    let headers_len = headers.len();
-    let headers_vec: Vec<String> = headers.iter().map(|s| s.to_string()).collect();
+impl RangeStrategy {
+    /// Loads fixed ranges from a `feature`, `xlow`, `xhigh` table
+    ///
+    /// `.tsv`/`.txt` files are read as tab separated, everything else as comma separated.
+    pub fn fixed_from_path<P: AsRef<Path>>(path: P) -> Result<Self, HistDiffError> {
+        let path = path.as_ref();
+        let delimiter = match path.extension().and_then(|e| e.to_str()) {
+            Some("tsv") | Some("txt") => b'\t',
+            _ => b',',
+        };
 
-    // get indices for id columns
-    let id_col_idx: Vec<usize> = config
-        .id_cols
-        .clone()
-        .iter()
-        .map(|i| headers_vec.iter().position(|h| i == h))
-        .collect::<Option<Vec<usize>>>()
-        .ok_or("id_col_idx: ID col(s) not found!")?;
-
-    // get indices for useless feats
-    let useless_idx: Option<Vec<usize>> = config.useless_cols.as_ref().map(|cols| {
-        cols.iter()
-            .filter_map(|i| headers_vec.iter().position(|h| h == i))
-            .collect()
-    });
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(true)
+            .from_reader(BufReader::new(File::open(path)?));
+
+        let headers: Vec<String> = reader.headers()?.iter().map(|h| h.to_string()).collect();
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| HistDiffError::MissingColumn {
+                    path: path.to_path_buf(),
+                    column: name.to_string(),
+                })
+        };
+        let (feat_idx, low_idx, high_idx) =
+            (position("feature")?, position("xlow")?, position("xhigh")?);
+
+        let mut ranges: HashMap<String, MinMax> = HashMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let value = |i: usize| -> Result<f64, HistDiffError> {
+                record[i].trim().parse::<f64>().map_err(|_| {
+                    HistDiffError::Parse(format!("invalid range bound '{}'", &record[i]))
+                })
+            };
+            let range = MinMax {
+                xlow: value(low_idx)?,
+                xhigh: value(high_idx)?,
+            };
+            ranges.insert(record[feat_idx].to_string(), range);
+        }
 
-    let feature_idx: Vec<usize> = (0..headers_len)
-        .filter(|i| {
-            !id_col_idx.contains(i)
-                && useless_idx
-                    .as_ref()
-                    .map_or(true, |useless| !useless.contains(i))
-        })
-        .collect();
+        let strategy = RangeStrategy::Fixed(ranges);
+        strategy.validate()?;
+        return Ok(strategy);
+    }
 
-    let mut feats: Vec<String> = feature_idx
-        .iter()
-        .map(|&x| headers_vec[x].clone())
-        .collect();
-    let xlow: DashMap<String, f64> = DashMap::new();
-    let xhigh: DashMap<String, f64> = DashMap::new();
+    /// Checks percentiles are ordered within 0-100 and fixed ranges are finite and non-empty
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        match self {
+            RangeStrategy::MinMax => Ok(()),
+            RangeStrategy::Percentile { low, high } => {
+                if (0.0..=100.0).contains(low) && (0.0..=100.0).contains(high) && low < high {
+                    Ok(())
+                } else {
+                    Err(HistDiffError::InvalidConfig(format!(
+                        "percentile range must satisfy 0 <= low < high <= 100, got {} and {}",
+                        low, high
+                    )))
+                }
+            }
+            RangeStrategy::Fixed(ranges) => {
+                match ranges
+                    .iter()
+                    .find(|(_, r)| !(r.xlow.is_finite() && r.xhigh.is_finite() && r.xlow < r.xhigh))
+                {
+                    Some((feat, r)) => Err(HistDiffError::InvalidConfig(format!(
+                        "fixed range of '{}' must be finite with xlow < xhigh, got [{}, {}]",
+                        feat, r.xlow, r.xhigh
+                    ))),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
 
-    for feat in &feats {
-        xlow.insert(feat.clone(), f64::NAN);
-        xhigh.insert(feat.clone(), f64::NAN);
+/// Which cells the histogram ranges are computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RangeCells {
+    #[default]
+    All,
+    /// Only cells of the vehicle control wells
+    Vehicles,
+}
+
+/// How the empty range `[c, c]` of a constant feature is widened around `c`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstantWidening {
+    /// `[c - w, c + w]`
+    Absolute(f64),
+    /// `[c - r * |c|, c + r * |c|]`, `[-r, r]` for a constant of 0
+    Relative(f64),
+}
+
+impl Default for ConstantWidening {
+    fn default() -> Self {
+        ConstantWidening::Absolute(0.5)
     }
+}
 
-    // NOTE: Read Start Time
-    let start_t = std::time::Instant::now();
-    if config.verbose {
-        info!("Beginning to read file for MIN_MAX");
+impl ConstantWidening {
+    /// Checks the widening is positive and finite
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        let (ConstantWidening::Absolute(w) | ConstantWidening::Relative(w)) = *self;
+        if w > 0.0 && w.is_finite() {
+            return Ok(());
+        }
+        return Err(HistDiffError::InvalidConfig(format!(
+            "constant widening must be positive, got {}",
+            w
+        )));
     }
 
-    for res in csv_reader.records() {
-        let record = res?;
-        if record.len() != headers_len {
-            continue;
+    /// Widened range around the constant `c`
+    pub fn widen(&self, c: f64) -> MinMax {
+        let w = match *self {
+            ConstantWidening::Absolute(w) => w,
+            ConstantWidening::Relative(r) if c == 0.0 => r,
+            ConstantWidening::Relative(r) => r * c.abs(),
+        };
+        // never narrower than a few ulps of `c`, so the bounds stay distinct
+        let w = w.max(c.abs() * 4.0 * f64::EPSILON);
+
+        return MinMax {
+            xlow: c - w,
+            xhigh: c + w,
+        };
+    }
+}
+
+/// Range strategy, the cells it looks at and what happens to values outside the range
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RangeOptions {
+    pub strategy: RangeStrategy,
+    pub cells: RangeCells,
+    pub out_of_range: OutOfRange,
+    pub constant: ConstantWidening,
+}
+
+impl RangeOptions {
+    /// Checks the strategy and the constant widening
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        self.strategy.validate()?;
+        return self.constant.validate();
+    }
+}
+
+/// retrieves the min max values for a given dataset
+pub fn get_min_max_plate(config: &UserConfig) -> Result<MinMaxPlateResult, HistDiffError> {
+    return min_max_from_source(config, &CellSource::Path);
+}
+
+/// Per-feature extrema of the finite values, before any adjustment
+#[derive(Debug, Clone)]
+pub(crate) struct RawExtrema {
+    pub features: Vec<String>,
+    pub low: Vec<f64>,
+    pub high: Vec<f64>,
+    /// number of finite values that went into `low`/`high`
+    pub finite: Vec<u64>,
+    /// one sketch per feature, only kept for `RangeStrategy::Percentile`
+    pub sketches: Option<Vec<QuantileSketch>>,
+    /// one set of statistics per feature, only kept for automatic bin rules
+    pub bin_stats: Option<Vec<BinStats>>,
+    /// columns the reader left out of `features`
+    pub excluded: Vec<ExcludedColumn>,
+    /// extrema over every row, only kept when the ranges look at some rows only
+    pub plate_extrema: Option<(Vec<f64>, Vec<f64>)>,
+}
+
+impl RawExtrema {
+    /// Empty extrema for `features`, with sketches when the config asks for percentiles
+    pub fn new(features: Vec<String>, config: &UserConfig) -> Self {
+        let sketches = match config.range.strategy {
+            RangeStrategy::Percentile { .. } => {
+                Some(vec![QuantileSketch::new(SKETCH_K); features.len()])
+            }
+            _ => None,
+        };
+        let bin_stats = match config.binning.rule {
+            BinRule::Fixed => None,
+            rule => Some(vec![BinStats::new(rule); features.len()]),
+        };
+
+        let plate_extrema = match config.range.cells {
+            RangeCells::All => None,
+            RangeCells::Vehicles => Some((
+                vec![f64::NAN; features.len()],
+                vec![f64::NAN; features.len()],
+            )),
+        };
+
+        RawExtrema {
+            low: vec![f64::NAN; features.len()],
+            high: vec![f64::NAN; features.len()],
+            finite: vec![0; features.len()],
+            features,
+            sketches,
+            bin_stats,
+            excluded: Vec::new(),
+            plate_extrema,
         }
+    }
 
-        feature_idx.par_iter().for_each(|&i| {
-            let feat = &headers_vec[i];
-            let field = &record[i];
+    /// Folds a batch of feature columns in
+    ///
+    /// Ranges only count the rows set in `rows` and bin statistics the rows set in
+    /// `bin_rows`, every row when `None`.
+    pub fn update(
+        &mut self,
+        columns: &[Vec<f64>],
+        rows: Option<&[bool]>,
+        bin_rows: Option<&[bool]>,
+    ) {
+        update_min_max(
+            &mut self.low,
+            &mut self.high,
+            &mut self.finite,
+            columns,
+            rows,
+        );
+
+        if let Some((low, high)) = &mut self.plate_extrema {
+            let mut finite = vec![0; columns.len()];
+            update_min_max(low, high, &mut finite, columns, None);
+        }
 
-            if let Ok(val) = field.parse::<f64>() {
-                if val.is_finite() {
-                    // min field
-                    xlow.entry(feat.to_string()).and_modify(|e| {
-                        if e.is_nan() {
-                            *e = val;
-                        } else {
-                            *e = e.min(val);
+        if let Some(bin_stats) = &mut self.bin_stats {
+            bin_stats
+                .par_iter_mut()
+                .zip(columns.par_iter())
+                .for_each(|(stats, column)| {
+                    for (r, &val) in column.iter().enumerate() {
+                        if bin_rows.is_none_or(|rows| rows[r]) {
+                            stats.insert(val);
                         }
-                    });
-
-                    // max field
-                    xhigh.entry(feat.to_string()).and_modify(|e| {
-                        if e.is_nan() {
-                            *e = val;
-                        } else {
-                            *e = e.max(val);
+                    }
+                });
+        }
+
+        if let Some(sketches) = &mut self.sketches {
+            sketches
+                .par_iter_mut()
+                .zip(columns.par_iter())
+                .for_each(|(sketch, column)| {
+                    for (r, &val) in column.iter().enumerate() {
+                        if rows.is_none_or(|rows| rows[r]) {
+                            sketch.insert(val);
                         }
-                    });
+                    }
+                });
+        }
+    }
+
+    /// Widens these extrema by another set, adding any features not seen yet
+    pub fn merge(&mut self, other: RawExtrema) {
+        for column in other.excluded {
+            if !self.excluded.iter().any(|c| c.column == column.column) {
+                self.excluded.push(column);
+            }
+        }
+
+        let mut other_plate = other
+            .plate_extrema
+            .map(|(low, high)| low.into_iter().zip(high));
+        let mut other_sketches = other.sketches.map(|s| s.into_iter());
+        let mut other_stats = other.bin_stats.map(|s| s.into_iter());
+        let columns = other.low.into_iter().zip(other.high).zip(other.finite);
+        for (feat, ((low, high), finite)) in other.features.into_iter().zip(columns) {
+            let sketch = other_sketches.as_mut().and_then(|s| s.next());
+            let stats = other_stats.as_mut().and_then(|s| s.next());
+            let plate = other_plate.as_mut().and_then(|p| p.next());
+            match self.features.iter().position(|f| *f == feat) {
+                Some(j) => {
+                    self.low[j] = self.low[j].min(low);
+                    self.high[j] = self.high[j].max(high);
+                    self.finite[j] += finite;
+                    if let (Some((lows, highs)), Some((low, high))) =
+                        (&mut self.plate_extrema, plate)
+                    {
+                        lows[j] = lows[j].min(low);
+                        highs[j] = highs[j].max(high);
+                    }
+                    if let (Some(sketches), Some(sketch)) = (&mut self.sketches, &sketch) {
+                        sketches[j].merge(sketch);
+                    }
+                    if let (Some(bin_stats), Some(stats)) = (&mut self.bin_stats, &stats) {
+                        bin_stats[j].merge(stats);
+                    }
+                }
+                None => {
+                    self.features.push(feat);
+                    self.low.push(low);
+                    self.high.push(high);
+                    self.finite.push(finite);
+                    if let (Some((lows, highs)), Some((low, high))) =
+                        (&mut self.plate_extrema, plate)
+                    {
+                        lows.push(low);
+                        highs.push(high);
+                    }
+                    if let (Some(sketches), Some(sketch)) = (&mut self.sketches, sketch) {
+                        sketches.push(sketch);
+                    }
+                    if let (Some(bin_stats), Some(stats)) = (&mut self.bin_stats, stats) {
+                        bin_stats.push(stats);
+                    }
                 }
             }
+        }
+    }
 
-            // nan is skipped
-        });
+    /// Adjusts the extrema into final histogram ranges according to `config.range`
+    /// and picks the bin count of every feature according to `config.binning`
+    ///
+    /// Fails when a problematic feature meets an `Error` action of `config.problems`.
+    pub fn finalize(self, config: &UserConfig) -> Result<MinMaxPlateResult, HistDiffError> {
+        let RawExtrema {
+            features,
+            low,
+            high,
+            finite,
+            sketches,
+            bin_stats,
+            excluded,
+            plate_extrema,
+        } = self;
+
+        // a single value in every cell of the plate, whatever the ranges end up as
+        let (plate_low, plate_high) = plate_extrema.unwrap_or_else(|| (low.clone(), high.clone()));
+        let constant: HashSet<String> = features
+            .iter()
+            .zip(plate_low.iter().zip(&plate_high))
+            .filter(|(_, (l, h))| l == h)
+            .map(|(feat, _)| feat.clone())
+            .collect();
+
+        // ranges picked by the strategy, applied after the features are classified
+        let mut ranges: HashMap<String, MinMax> = HashMap::new();
+        match (&config.range.strategy, sketches) {
+            (
+                RangeStrategy::Percentile {
+                    low: p_low,
+                    high: p_high,
+                },
+                Some(sketches),
+            ) => {
+                for (feat, sketch) in features.iter().zip(&sketches) {
+                    if let (Some(l), Some(h)) = (
+                        sketch.quantile(p_low / 100.0),
+                        sketch.quantile(p_high / 100.0),
+                    ) {
+                        ranges.insert(feat.clone(), MinMax { xlow: l, xhigh: h });
+                    }
+                }
+            }
+            (RangeStrategy::Fixed(fixed), _) => {
+                for (j, feat) in features.iter().enumerate() {
+                    // features without finite data stay problematic
+                    if let (Some(range), false) = (fixed.get(feat), low[j].is_nan()) {
+                        ranges.insert(feat.clone(), range.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let xlow: DashMap<String, f64> = features.iter().cloned().zip(low).collect();
+        let xhigh: DashMap<String, f64> = features.iter().cloned().zip(high).collect();
+        let finite: HashMap<String, u64> = features.iter().cloned().zip(finite).collect();
+
+        let mut stats: HashMap<String, BinStats> = match bin_stats {
+            Some(bin_stats) => features.iter().cloned().zip(bin_stats).collect(),
+            None => HashMap::new(),
+        };
+
+        let mut res = finalize_min_max(features, xlow, xhigh, &finite, ranges, config)?;
+        res.excluded_columns = excluded;
+        res.constant_features = res
+            .features
+            .iter()
+            .filter(|feat| constant.contains(*feat))
+            .cloned()
+            .collect();
+        for (j, (feat, range)) in res.min_max.iter().enumerate() {
+            // features without statistics (no selected cells) keep `config.nbins`
+            if let Some(nbins) = stats
+                .remove(feat)
+                .and_then(|s| s.bins(&config.binning, config.nbins, range.xhigh - range.xlow))
+            {
+                res.nbins[j] = nbins;
+            }
+        }
+
+        if config.verbose && config.binning.rule != BinRule::Fixed {
+            info!("bin counts: {:?}", res.nbins);
+        }
+
+        return Ok(res);
+    }
+}
+
+/// Rows of a batch that count towards the ranges, `None` when every row does
+///
+/// `vehicles` is the normalized vehicle well set, only used for `RangeCells::Vehicles`.
+pub(crate) fn range_rows(
+    config: &UserConfig,
+    vehicles: &HashSet<String>,
+    wells: &[String],
+) -> Option<Vec<bool>> {
+    return cell_rows(config.range.cells, vehicles, wells);
+}
+
+/// Rows of a batch that count towards the automatic bin counts, `None` when every row does
+pub(crate) fn bin_rows(
+    config: &UserConfig,
+    vehicles: &HashSet<String>,
+    wells: &[String],
+) -> Option<Vec<bool>> {
+    return cell_rows(config.binning.cells, vehicles, wells);
+}
+
+fn cell_rows(cells: RangeCells, vehicles: &HashSet<String>, wells: &[String]) -> Option<Vec<bool>> {
+    match cells {
+        RangeCells::All => None,
+        RangeCells::Vehicles => Some(wells.iter().map(|w| vehicles.contains(w)).collect()),
+    }
+}
+
+/// retrieves the min max values for the cell data behind `source`
+pub(crate) fn min_max_from_source(
+    config: &UserConfig,
+    source: &CellSource,
+) -> Result<MinMaxPlateResult, HistDiffError> {
+    return raw_min_max(config, source)?.finalize(config);
+}
+
+/// Reads the unadjusted extrema of every feature behind `source`
+pub(crate) fn raw_min_max(
+    config: &UserConfig,
+    source: &CellSource,
+) -> Result<RawExtrema, HistDiffError> {
+    if config.verbose {
+        info!("Starting Min Max Process for all specified features.");
+    }
+
+    config.range.validate()?;
+    config.binning.validate()?;
+    let vehicles = well_set(&config.vehicle_cntrls);
+
+    let mut reader = source.open(config)?;
+    let mut extrema = RawExtrema::new(reader.features.clone(), config);
+    extrema.excluded = reader.excluded.clone();
+
+    // NOTE: Read Start Time
+    let start_t = std::time::Instant::now();
+    if config.verbose {
+        info!("Beginning to read file for MIN_MAX");
+    }
+
+    while let Some(batch) = reader.next_batch()? {
+        let rows = range_rows(config, &vehicles, &batch.wells);
+        let bin_rows = bin_rows(config, &vehicles, &batch.wells);
+        extrema.update(&batch.columns, rows.as_deref(), bin_rows.as_deref());
     }
 
     // NOTE: End of start time
@@ -165,89 +696,154 @@ pub fn get_min_max_plate(config: &UserConfig) -> Result<MinMaxPlateResult, Box<d
         info!("End of reading MIN_MAX. Time: {:?}", start_t.elapsed());
     }
 
+    return Ok(extrema);
+}
+
+/// Folds a batch of feature columns into the running extrema
+///
+/// Non-finite values and rows not set in `rows` are skipped; `low`/`high` start out as `NaN`
+/// and `finite` counts the values that were taken into account.
+pub(crate) fn update_min_max(
+    low: &mut [f64],
+    high: &mut [f64],
+    finite: &mut [u64],
+    columns: &[Vec<f64>],
+    rows: Option<&[bool]>,
+) {
+    low.par_iter_mut()
+        .zip(high.par_iter_mut())
+        .zip(finite.par_iter_mut())
+        .zip(columns.par_iter())
+        .for_each(|(((low, high), finite), column)| {
+            for (r, &val) in column.iter().enumerate() {
+                if val.is_finite() && rows.is_none_or(|rows| rows[r]) {
+                    // f64::min/max return the other operand when one side is NaN
+                    *low = low.min(val);
+                    *high = high.max(val);
+                    *finite += 1;
+                }
+            }
+        });
+}
+
+/// Adjusts the raw per-feature extrema and packs them into a `MinMaxPlateResult`
+///
+/// Shared by the two-pass reader and the single-pass reader so both end up
+/// with identical ranges for identical data. Problematic features are classified on the
+/// unadjusted extrema and dropped, kept or rejected according to `config.problems`.
+/// `ranges` then replaces the extrema of the features the range strategy picked a range for.
+pub(crate) fn finalize_min_max(
+    mut feats: Vec<String>,
+    xlow: DashMap<String, f64>,
+    xhigh: DashMap<String, f64>,
+    finite: &HashMap<String, u64>,
+    ranges: HashMap<String, MinMax>,
+    config: &UserConfig,
+) -> Result<MinMaxPlateResult, HistDiffError> {
     // NOTE: Start of Adjustment and Exporting
     let start_t = std::time::Instant::now();
     if config.verbose {
         info!("Starting MIN_MAX calculations and adjustments.");
     }
 
-    adjust_min_max(&xlow, &xhigh, &feats);
+    // find problematic features before the ranges get adjusted
+    let mut problematic_features: Vec<ProblematicFeature> = Vec::new();
+    for feat in &feats {
+        let low = xlow.get(feat).map(|v| *v).unwrap_or(f64::NAN);
+        let high = xhigh.get(feat).map(|v| *v).unwrap_or(f64::NAN);
+        let count = finite.get(feat).copied().unwrap_or(0);
+
+        if let Some(problem) = config.problems.classify(count, low, high) {
+            let action = config.problems.action(problem);
+            if action == ProblemAction::Error {
+                return Err(HistDiffError::ProblematicFeature {
+                    feature: feat.clone(),
+                    problem,
+                });
+            }
+
+            problematic_features.push(ProblematicFeature {
+                feature: feat.clone(),
+                problem,
+                finite: count,
+                action,
+            });
+        }
+    }
+
+    for (feat, range) in ranges {
+        xlow.insert(feat.clone(), range.xlow);
+        xhigh.insert(feat, range.xhigh);
+    }
+    adjust_min_max(&xlow, &xhigh, &feats, config.range.constant);
 
     let xlow: HashMap<String, f64> = xlow.into_iter().collect();
     let xhigh: HashMap<String, f64> = xhigh.into_iter().collect();
 
-    // drop and find problematic features
-    let mut problematic_features: HashSet<String> = HashSet::new();
-    for feat in &feats {
-        let low = *xlow.get(feat).unwrap();
-        let high = *xlow.get(feat).unwrap();
-        if low.is_nan() && high.is_nan() {
-            problematic_features.insert(feat.clone());
-        }
-    }
+    let dropped: HashSet<&str> = problematic_features
+        .iter()
+        .filter(|p| p.action == ProblemAction::Drop)
+        .map(|p| p.feature.as_str())
+        .collect();
 
     let mut min_max_vec: Vec<(String, MinMax)> = Vec::new();
     for feat in &feats {
-        if !problematic_features.contains(feat) {
-            let low = xlow.get(feat).unwrap();
-            let high = xhigh.get(feat).unwrap();
+        if !dropped.contains(feat.as_str()) {
+            let low = *xlow.get(feat).unwrap();
+            let high = *xhigh.get(feat).unwrap();
 
-            min_max_vec.push((
-                feat.clone(),
+            // kept features without any finite value still need a usable range
+            let range = if low.is_nan() || high.is_nan() {
                 MinMax {
-                    xlow: *low,
-                    xhigh: *high,
-                },
-            ));
+                    xlow: 0.0,
+                    xhigh: 1.0,
+                }
+            } else {
+                MinMax {
+                    xlow: low,
+                    xhigh: high,
+                }
+            };
+            min_max_vec.push((feat.clone(), range));
         }
     }
 
-    //remove problematic_features
-    feats.retain(|feat| !problematic_features.contains(feat));
-
-    let problematic_features_vec = if !problematic_features.is_empty() {
-        let prob_feats_list: Vec<String> = problematic_features.into_iter().collect();
-        Some(prob_feats_list)
-    } else {
-        None
-    };
+    //remove dropped features
+    feats.retain(|feat| !dropped.contains(feat.as_str()));
 
     // NOTE: End of start time
     if config.verbose {
         info!("End of processing. Time: {:?}", start_t.elapsed());
-    }
-
-    if config.verbose {
-        if let Some(ref prob_vec) = problematic_features_vec {
-            info!("len bad features: {}", prob_vec.len());
-        }
+        info!("len bad features: {}", problematic_features.len());
         info!("len of good feats: {}", feats.len())
     }
 
     let res = MinMaxPlateResult {
+        nbins: vec![config.nbins; min_max_vec.len()],
+        excluded_columns: Vec::new(),
+        constant_features: Vec::new(),
         min_max: min_max_vec,
         features: feats,
-        problemativ_features: problematic_features_vec,
+        problematic_features,
     };
 
     return Ok(res);
 }
 
-/// Adjusts the min max values
-fn adjust_min_max(xlow: &DashMap<String, f64>, xhigh: &DashMap<String, f64>, feats: &[String]) {
+/// Widens the ranges of constant features according to `widening`
+fn adjust_min_max(
+    xlow: &DashMap<String, f64>,
+    xhigh: &DashMap<String, f64>,
+    feats: &[String],
+    widening: ConstantWidening,
+) {
     feats.par_iter().for_each(|feat| {
         let low = xlow.get(feat).map(|v| *v).unwrap_or(f64::NAN);
         let high = xhigh.get(feat).map(|v| *v).unwrap_or(f64::NAN);
-        if low.is_nan() || high.is_nan() {
-            return;
-        } else if low == high {
-            let adjust_high = if low != 0.0 {
-                low + low + 0.5
-            } else {
-                low + 1.0
-            };
-
-            xhigh.insert(feat.clone(), adjust_high);
+        if low == high {
+            let range = widening.widen(low);
+            xlow.insert(feat.clone(), range.xlow);
+            xhigh.insert(feat.clone(), range.xhigh);
         }
     });
 }
@@ -0,0 +1,26 @@
+/// What happens to the scores of a well with fewer cells than `CellCountOptions.min_cells`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LowCellAction {
+    /// Keeps the scores and lists the well in `HistDiffRes::low_cell_wells`
+    #[default]
+    Flag,
+    /// Replaces every score of the well with `NaN` (the well is still listed)
+    Mask,
+}
+
+/// Per-well cell counts in the output and the minimum cell count of a scored well
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CellCountOptions {
+    /// adds `cells` and `vehicle_cells` columns after the id columns
+    pub columns: bool,
+    /// wells with fewer cell rows are flagged or masked, 0 disables the check
+    pub min_cells: usize,
+    pub action: LowCellAction,
+}
+
+impl CellCountOptions {
+    /// Whether a well with `cells` cell rows is under-populated
+    pub fn is_low(&self, cells: usize) -> bool {
+        return cells < self.min_cells;
+    }
+}
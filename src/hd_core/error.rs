@@ -0,0 +1,131 @@
+use polars::prelude::PolarsError;
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use super::problems::FeatureProblem;
+
+/// Errors returned by the HistDiff public API
+///
+/// Block indices refer to positions in `UserConfig.block_def`.
+#[derive(Debug)]
+pub enum HistDiffError {
+    /// An entry of `id_cols` is not a column of the input
+    MissingIdColumn(String),
+    /// None of the vehicle control wells have data in the given block
+    MissingControls { block: usize },
+    /// None of the wells of the given block have data
+    EmptyBlock { block: usize },
+    /// A required column is missing from an auxiliary table, such as a platemap or ranges file
+    MissingColumn { path: PathBuf, column: String },
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// The input could not be parsed
+    Parse(String),
+    /// A polars operation failed
+    Polars(PolarsError),
+    /// A `UserConfig` option is out of its valid range
+    InvalidConfig(String),
+    /// A feature has a problem whose `ProblemPolicy` action is `Error`
+    ProblematicFeature {
+        feature: String,
+        problem: FeatureProblem,
+    },
+    /// A histogram was asked for no bins or an empty/non-finite range
+    InvalidHistogram { nbins: usize, xlow: f64, xhigh: f64 },
+    /// Two inputs that must line up do not
+    ShapeMismatch { expected: usize, found: usize },
+    /// Two histograms that are added together cover different (xlow, xhigh) ranges
+    RangeMismatch {
+        expected: (f64, f64),
+        found: (f64, f64),
+    },
+    /// A feature scored over the whole campaign is not a column of a plate
+    MissingFeature(String),
+    /// Two plates of a batch resolve to the same plate key
+    DuplicatePlate(String),
+    /// Scoring one plate of a batch failed
+    Plate {
+        plate: String,
+        source: Box<HistDiffError>,
+    },
+}
+
+impl fmt::Display for HistDiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistDiffError::MissingIdColumn(col) => {
+                write!(f, "ID column '{}' not found in headers", col)
+            }
+            HistDiffError::MissingControls { block } => {
+                write!(f, "no vehicle control wells found in block {}", block)
+            }
+            HistDiffError::EmptyBlock { block } => {
+                write!(f, "no wells with data found in block {}", block)
+            }
+            HistDiffError::MissingColumn { path, column } => {
+                write!(f, "column '{}' not found in {}", column, path.display())
+            }
+            HistDiffError::Io(err) => write!(f, "I/O error: {}", err),
+            HistDiffError::Parse(msg) => write!(f, "parse error: {}", msg),
+            HistDiffError::Polars(err) => write!(f, "polars error: {}", err),
+            HistDiffError::InvalidConfig(msg) => write!(f, "invalid config: {}", msg),
+            HistDiffError::ProblematicFeature { feature, problem } => {
+                write!(f, "feature '{}': {}", feature, problem)
+            }
+            HistDiffError::InvalidHistogram { nbins, xlow, xhigh } => write!(
+                f,
+                "invalid histogram: {} bins over [{}, {}]",
+                nbins, xlow, xhigh
+            ),
+            HistDiffError::ShapeMismatch { expected, found } => {
+                write!(f, "shape mismatch: expected {}, found {}", expected, found)
+            }
+            HistDiffError::MissingFeature(feature) => {
+                write!(f, "feature '{}' not found in headers", feature)
+            }
+            HistDiffError::DuplicatePlate(plate) => {
+                write!(f, "plate '{}' appears more than once in the batch", plate)
+            }
+            HistDiffError::RangeMismatch { expected, found } => write!(
+                f,
+                "range mismatch: expected [{}, {}], found [{}, {}]",
+                expected.0, expected.1, found.0, found.1
+            ),
+            HistDiffError::Plate { plate, source } => write!(f, "plate {}: {}", plate, source),
+        }
+    }
+}
+
+impl Error for HistDiffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HistDiffError::Io(err) => Some(err),
+            HistDiffError::Polars(err) => Some(err),
+            HistDiffError::Plate { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HistDiffError {
+    fn from(err: io::Error) -> Self {
+        HistDiffError::Io(err)
+    }
+}
+
+impl From<PolarsError> for HistDiffError {
+    fn from(err: PolarsError) -> Self {
+        HistDiffError::Polars(err)
+    }
+}
+
+impl From<csv::Error> for HistDiffError {
+    fn from(err: csv::Error) -> Self {
+        if err.is_io_error() {
+            match err.into_kind() {
+                csv::ErrorKind::Io(io_err) => return HistDiffError::Io(io_err),
+                kind => return HistDiffError::Parse(format!("{:?}", kind)),
+            }
+        }
+        HistDiffError::Parse(err.to_string())
+    }
+}
@@ -4,15 +4,80 @@ use core::f64;
 use ndarray::{Array1, Array2, Axis};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use std::collections::{HashMap, HashSet};
-use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
-use std::usize;
 
-use super::calculations::{exponential_smoothing, normalize};
+use super::calculations::{
+    exponential_smoothing, gaussian_smoothing, moving_average, normalize, savitzky_golay,
+};
+use super::error::HistDiffError;
 use super::utils::UserConfig;
 
+/// Smoothing applied to every histogram before it is normalized and scored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Blends every bin with its two neighbours, `alpha` each (the original HistDiff smoothing)
+    Exponential {
+        alpha: f64,
+    },
+    /// Gaussian kernel, `sigma` in bins
+    Gaussian {
+        sigma: f64,
+    },
+    /// Centered moving average over `k` bins
+    MovingAverage {
+        k: usize,
+    },
+    /// Local polynomial fit of degree `order` over an odd `window` of bins
+    SavitzkyGolay {
+        window: usize,
+        order: usize,
+    },
+    None,
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing::Exponential { alpha: 0.25 }
+    }
+}
+
+impl Smoothing {
+    /// Checks the smoothing parameters
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        let invalid = |msg: String| Err(HistDiffError::InvalidConfig(msg));
+        match *self {
+            Smoothing::Exponential { alpha } if !(0.0..=0.5).contains(&alpha) => {
+                invalid(format!("smoothing alpha must be in [0, 0.5], got {}", alpha))
+            }
+            Smoothing::Gaussian { sigma } if !(sigma > 0.0 && sigma.is_finite()) => {
+                invalid(format!("gaussian sigma must be positive, got {}", sigma))
+            }
+            Smoothing::MovingAverage { k: 0 } => {
+                invalid("moving average width must be at least 1".to_string())
+            }
+            Smoothing::SavitzkyGolay { window, order } if window % 2 == 0 || order >= window => {
+                invalid(format!(
+                    "savitzky-golay needs an odd window larger than the order, got window {} and order {}",
+                    window, order
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What `Hist1D::fill_with` does with finite values outside `[xlow, xhigh]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutOfRange {
+    /// Keeps them out of the bins and counts them as underflow/overflow
+    #[default]
+    Separate,
+    /// Puts them into the first or last bin
+    Clamp,
+}
+
 /// A struct/interface to handle with histogram operations
 #[derive(Clone, Debug)]
 pub struct Hist1D {
@@ -22,6 +87,27 @@ pub struct Hist1D {
     pub bin_width: f64,
     pub bins: Vec<f64>,
     pub counts: Vec<f64>,
+    qc: HistCounts,
+}
+
+/// Bookkeeping of every value passed to `Hist1D::fill`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HistCounts {
+    /// every value, binned or not
+    pub entries: u64,
+    /// values below `xlow` (including `-inf`)
+    pub underflow: u64,
+    /// values above `xhigh` (including `inf`)
+    pub overflow: u64,
+    /// NaN values, e.g. missing or non-numeric cells
+    pub nan: u64,
+}
+
+impl HistCounts {
+    /// Values that landed in a bin
+    pub fn in_range(&self) -> u64 {
+        self.entries - self.underflow - self.overflow - self.nan
+    }
 }
 
 impl Hist1D {
@@ -31,20 +117,27 @@ impl Hist1D {
     /// - nbins => number of bins to use in a histogram
     /// - xlow => the minimum range of values to use in a histogram
     /// - xhigh => the maximium range of values to use in a histogram
-    pub fn new(nbins: usize, xlow: f64, xhigh: f64) -> Self {
+    ///
+    /// Errors unless `nbins > 0` and `xlow < xhigh` are both finite.
+    pub fn new(nbins: usize, xlow: f64, xhigh: f64) -> Result<Self, HistDiffError> {
+        if nbins == 0 || !(xlow.is_finite() && xhigh.is_finite() && xlow < xhigh) {
+            return Err(HistDiffError::InvalidHistogram { nbins, xlow, xhigh });
+        }
+
         let bin_width = (xhigh - xlow) / nbins as f64;
         let bins = (0..nbins)
             .map(|i| xlow + (i as f64 + 0.5) * bin_width)
             .collect();
         let counts = vec![0 as f64; nbins];
-        return Hist1D {
+        return Ok(Hist1D {
             nbins,
             xlow,
             xhigh,
             bin_width,
             bins,
             counts,
-        };
+            qc: HistCounts::default(),
+        });
     }
 
     /// Fills and places the histogram into the appropriate bins in the histogram
@@ -53,18 +146,65 @@ impl Hist1D {
     /// - data => given a vector of floats, bin each value into the appropriate histogram bin
     pub fn fill(&mut self, data: &[f64]) {
         for &value in data {
+            self.qc.entries += 1;
             if value >= self.xlow && value < self.xhigh {
                 let bin_index = ((value - self.xlow) / self.bin_width) as usize;
                 self.counts[bin_index] += 1.0;
             } else if value == self.xhigh {
                 // upper bound must be in last bin
                 self.counts[self.nbins - 1] += 1.0;
+            } else if value < self.xlow {
+                self.qc.underflow += 1;
+            } else if value > self.xhigh {
+                self.qc.overflow += 1;
+            } else {
+                self.qc.nan += 1;
             }
+        }
+    }
 
-            // any values out of range get ignored
+    /// Fills the histogram, handling out of range values as `out_of_range` says
+    pub fn fill_with(&mut self, data: &[f64], out_of_range: OutOfRange) {
+        match out_of_range {
+            OutOfRange::Separate => self.fill(data),
+            OutOfRange::Clamp => {
+                for &value in data {
+                    let value = if value.is_finite() {
+                        value.clamp(self.xlow, self.xhigh)
+                    } else {
+                        value
+                    };
+                    self.fill(&[value]);
+                }
+            }
         }
     }
 
+    /// Number of values passed to `fill`, binned or not
+    pub fn entries(&self) -> u64 {
+        self.qc.entries
+    }
+
+    /// Number of values below `xlow`
+    pub fn underflow(&self) -> u64 {
+        self.qc.underflow
+    }
+
+    /// Number of values above `xhigh`
+    pub fn overflow(&self) -> u64 {
+        self.qc.overflow
+    }
+
+    /// Number of NaN values
+    pub fn nan_count(&self) -> u64 {
+        self.qc.nan
+    }
+
+    /// All of the above at once
+    pub fn qc_counts(&self) -> HistCounts {
+        self.qc
+    }
+
     /// Returns (bins [0], counts [1])
     pub fn data(&self) -> (&[f64], &[f64]) {
         (&self.bins, &self.counts)
@@ -78,20 +218,50 @@ impl Hist1D {
         self.counts = exponential_smoothing(&self.counts, alpha);
     }
 
+    /// Smoothens the histograms with any `Smoothing` kernel
+    pub fn smooth_with(&mut self, smoothing: &Smoothing) {
+        self.counts = match *smoothing {
+            Smoothing::Exponential { alpha } => exponential_smoothing(&self.counts, alpha),
+            Smoothing::Gaussian { sigma } => gaussian_smoothing(&self.counts, sigma),
+            Smoothing::MovingAverage { k } => moving_average(&self.counts, k),
+            Smoothing::SavitzkyGolay { window, order } => {
+                savitzky_golay(&self.counts, window, order)
+            }
+            Smoothing::None => return,
+        };
+    }
+
     /// Normalizes the histograms into manageable values
     pub fn normalize(&mut self) {
         self.counts = normalize(&self.counts)
     }
 
     /// Adds 2 histograms together
-    pub fn add(&mut self, other: &Hist1D) {
-        assert_eq!(self.nbins, other.nbins);
-        assert_eq!(self.xlow, other.xlow);
-        assert_eq!(self.xhigh, self.xhigh);
+    ///
+    /// Fails with `HistDiffError::ShapeMismatch` when the bin counts differ and with
+    /// `HistDiffError::RangeMismatch` when the ranges differ.
+    pub fn add(&mut self, other: &Hist1D) -> Result<(), HistDiffError> {
+        if self.nbins != other.nbins {
+            return Err(HistDiffError::ShapeMismatch {
+                expected: self.nbins,
+                found: other.nbins,
+            });
+        }
+        if self.xlow != other.xlow || self.xhigh != other.xhigh {
+            return Err(HistDiffError::RangeMismatch {
+                expected: (self.xlow, self.xhigh),
+                found: (other.xlow, other.xhigh),
+            });
+        }
 
         for (c1, c2) in self.counts.iter_mut().zip(other.counts.iter()) {
             *c1 += c2;
         }
+        self.qc.entries += other.qc.entries;
+        self.qc.underflow += other.qc.underflow;
+        self.qc.overflow += other.qc.overflow;
+        self.qc.nan += other.qc.nan;
+        return Ok(());
     }
 }
 
@@ -100,10 +270,13 @@ pub fn hist_square_diff_deprecated(
     exp: &Array2<f64>,
     ctrl: &Array1<f64>,
     factor: f64,
-) -> Result<Array1<f64>, Box<dyn Error>> {
+) -> Result<Array1<f64>, HistDiffError> {
     // shape check
     if exp.shape()[0] != ctrl.len() {
-        return Err("Input arrays must have the same shape".into());
+        return Err(HistDiffError::ShapeMismatch {
+            expected: ctrl.len(),
+            found: exp.shape()[0],
+        });
     }
 
     //ctrl mean proxy
@@ -144,18 +317,29 @@ pub fn hist_square_diff_deprecated(
 /// - cntrl => the control vector
 /// - factor => the magnitute to apply on the result
 pub fn hist_square_diff(
-    exp: &Vec<Vec<f64>>,
-    ctrl: &Vec<f64>,
+    exp: &[Vec<f64>],
+    ctrl: &[f64],
     factor: f64,
-) -> Result<Vec<f64>, Box<dyn Error>> {
+) -> Result<Vec<f64>, HistDiffError> {
+    // every well needs one value per control bin
+    if let Some(row) = exp.iter().find(|row| row.len() != ctrl.len()) {
+        return Err(HistDiffError::ShapeMismatch {
+            expected: ctrl.len(),
+            found: row.len(),
+        });
+    }
+
     // transpose input matrix
     let exp = transpose_2d_vec(exp);
 
     let num_rows = exp.len();
-    let num_cols = exp.get(0).map(|row| row.len()).unwrap_or(0);
+    let num_cols = exp.first().map(|row| row.len()).unwrap_or(0);
 
     if num_rows == 0 || num_cols == 0 || ctrl.len() != num_rows {
-        return Err("Input vectors  must have matching shapes".into());
+        return Err(HistDiffError::ShapeMismatch {
+            expected: ctrl.len(),
+            found: num_rows,
+        });
     }
 
     let ctrl_indices: Vec<f64> = (1..=num_rows).map(|x| x as f64).collect();
@@ -198,7 +382,7 @@ pub fn hist_square_diff(
 }
 
 /// tranposes a matrix from m x n to n x m
-fn transpose_2d_vec(matrix: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+fn transpose_2d_vec(matrix: &[Vec<f64>]) -> Vec<Vec<f64>> {
     let n_rows = matrix.len();
     if n_rows == 0 {
         return Vec::new();
@@ -0,0 +1,189 @@
+use core::f64;
+
+use super::error::HistDiffError;
+
+/// Floor applied to control probabilities in the KL divergence so empty bins stay finite
+const KL_EPSILON: f64 = 1e-10;
+
+/// A distance between two normalized histograms over the same bins
+///
+/// Implementations return an unsigned distance; `metric_scores` adds the HistDiff
+/// sign (experimental mean proxy above/below the control) when asked to.
+pub trait DistanceMetric: Send + Sync {
+    /// Short name used in the output column names, e.g. "ks"
+    fn name(&self) -> &'static str;
+
+    /// Distance of one experimental histogram from the control histogram
+    fn distance(&self, exp: &[f64], ctrl: &[f64]) -> f64;
+}
+
+/// The distance metrics `calculate_scores` can emit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Metric {
+    /// Sum of squared bin differences, the original HistDiff score
+    #[default]
+    HistDiff,
+    /// Largest absolute difference of the cumulative histograms
+    KolmogorovSmirnov,
+    /// 1-D earth mover's distance, in bins
+    Wasserstein,
+    /// Jensen-Shannon divergence (natural log, at most ln 2)
+    JensenShannon,
+    /// Kullback-Leibler divergence of the experimental histogram from the control
+    KullbackLeibler,
+    Hellinger,
+    Bhattacharyya,
+    /// Symmetric chi-square distance `sum (p - q)^2 / (p + q)`
+    ChiSquare,
+}
+
+impl DistanceMetric for Metric {
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::HistDiff => "hd",
+            Metric::KolmogorovSmirnov => "ks",
+            Metric::Wasserstein => "wasserstein",
+            Metric::JensenShannon => "js",
+            Metric::KullbackLeibler => "kl",
+            Metric::Hellinger => "hellinger",
+            Metric::Bhattacharyya => "bhattacharyya",
+            Metric::ChiSquare => "chi2",
+        }
+    }
+
+    fn distance(&self, exp: &[f64], ctrl: &[f64]) -> f64 {
+        match self {
+            Metric::HistDiff => exp.iter().zip(ctrl).map(|(e, c)| (c - e).powi(2)).sum(),
+            Metric::KolmogorovSmirnov => cdf_differences(exp, ctrl).fold(0.0, f64::max),
+            Metric::Wasserstein => cdf_differences(exp, ctrl).sum(),
+            Metric::JensenShannon => {
+                let mid: Vec<f64> = exp.iter().zip(ctrl).map(|(e, c)| 0.5 * (e + c)).collect();
+                0.5 * kl_divergence(exp, &mid) + 0.5 * kl_divergence(ctrl, &mid)
+            }
+            Metric::KullbackLeibler => kl_divergence(exp, ctrl),
+            Metric::Hellinger => (1.0 - bhattacharyya_coefficient(exp, ctrl)).max(0.0).sqrt(),
+            Metric::Bhattacharyya => -bhattacharyya_coefficient(exp, ctrl).ln(),
+            Metric::ChiSquare => exp
+                .iter()
+                .zip(ctrl)
+                .filter(|(e, c)| *e + *c > 0.0)
+                .map(|(e, c)| (e - c).powi(2) / (e + c))
+                .sum(),
+        }
+    }
+}
+
+/// One metric selected for the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MetricSpec {
+    pub metric: Metric,
+    /// negate the distance when the experimental mean proxy is below the control's
+    pub signed: bool,
+}
+
+impl Default for MetricSpec {
+    fn default() -> Self {
+        MetricSpec {
+            metric: Metric::HistDiff,
+            signed: true,
+        }
+    }
+}
+
+impl MetricSpec {
+    pub fn signed(metric: Metric) -> Self {
+        MetricSpec {
+            metric,
+            signed: true,
+        }
+    }
+
+    pub fn unsigned(metric: Metric) -> Self {
+        MetricSpec {
+            metric,
+            signed: false,
+        }
+    }
+
+    /// Output column of a feature under this metric
+    ///
+    /// The default signed HistDiff keeps the bare feature name; every other selection
+    /// is suffixed, e.g. `f_a_ks` or `f_a_hd_unsigned`.
+    pub fn column_name(&self, feature: &str) -> String {
+        if *self == MetricSpec::default() {
+            return feature.to_string();
+        }
+
+        return match self.signed {
+            true => format!("{}_{}", feature, self.metric.name()),
+            false => format!("{}_{}_unsigned", feature, self.metric.name()),
+        };
+    }
+}
+
+/// Scores every experimental histogram against the control with any `DistanceMetric`
+///
+/// # params:
+/// - exp => one normalized histogram per well
+/// - ctrl => the normalized control histogram
+/// - signed => apply the HistDiff mean proxy sign
+pub fn metric_scores(
+    metric: &dyn DistanceMetric,
+    exp: &[Vec<f64>],
+    ctrl: &[f64],
+    signed: bool,
+) -> Result<Vec<f64>, HistDiffError> {
+    if let Some(row) = exp.iter().find(|row| row.len() != ctrl.len()) {
+        return Err(HistDiffError::ShapeMismatch {
+            expected: ctrl.len(),
+            found: row.len(),
+        });
+    }
+
+    return Ok(exp
+        .iter()
+        .map(|row| {
+            let distance = metric.distance(row, ctrl);
+            match signed {
+                true => mean_proxy_sign(row, ctrl) * distance,
+                false => distance,
+            }
+        })
+        .collect());
+}
+
+/// -1.0 when the control's mean proxy (`sum(count * bin number)`) is above the experimental one
+pub fn mean_proxy_sign(exp: &[f64], ctrl: &[f64]) -> f64 {
+    let mean_proxy = |hist: &[f64]| -> f64 {
+        hist.iter()
+            .enumerate()
+            .map(|(i, c)| c * (i + 1) as f64)
+            .sum()
+    };
+
+    return if mean_proxy(ctrl) > mean_proxy(exp) {
+        -1.0
+    } else {
+        1.0
+    };
+}
+
+/// |CDF_exp - CDF_ctrl| at every bin
+fn cdf_differences<'a>(exp: &'a [f64], ctrl: &'a [f64]) -> impl Iterator<Item = f64> + 'a {
+    exp.iter().zip(ctrl).scan(0.0, |cum, (e, c)| {
+        *cum += e - c;
+        Some(cum.abs())
+    })
+}
+
+fn kl_divergence(p: &[f64], q: &[f64]) -> f64 {
+    p.iter()
+        .zip(q)
+        .filter(|(p, _)| **p > 0.0)
+        .map(|(p, q)| p * (p / q.max(KL_EPSILON)).ln())
+        .sum()
+}
+
+fn bhattacharyya_coefficient(p: &[f64], q: &[f64]) -> f64 {
+    p.iter().zip(q).map(|(p, q)| (p * q).sqrt()).sum()
+}
@@ -1,3 +1,17 @@
+pub mod binning;
 pub mod calculations;
+pub mod cells;
+pub mod error;
 pub mod histograms;
+pub mod metrics;
+pub mod platemap;
+pub mod pooling;
+pub mod problems;
+pub mod reader;
+pub mod selection;
+pub mod significance;
+pub mod sketch;
+pub mod standardize;
+pub mod transform;
 pub mod utils;
+pub mod well;
@@ -0,0 +1,254 @@
+use polars::prelude::*;
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use super::{error::HistDiffError, utils::UserConfig, well::normalize_well};
+
+/// Column names looked up when parsing a platemap
+///
+/// The well and sample type columns are required; the others are read when present.
+#[derive(Debug, Clone)]
+pub struct PlateMapColumns {
+    pub well: String,
+    pub sample_type: String,
+    pub compound: String,
+    pub concentration: String,
+    pub block: String,
+}
+
+impl Default for PlateMapColumns {
+    fn default() -> Self {
+        PlateMapColumns {
+            well: "384_Well".to_string(),
+            sample_type: "sample_type".to_string(),
+            compound: "compound".to_string(),
+            concentration: "concentration".to_string(),
+            block: "block".to_string(),
+        }
+    }
+}
+
+/// Annotations of one well
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WellAnnotation {
+    pub sample_type: Option<String>,
+    pub compound: Option<String>,
+    pub concentration: Option<f64>,
+    pub block: Option<String>,
+}
+
+/// Maps the wells of a plate to their sample type, compound, concentration and block
+#[derive(Debug, Clone, Default)]
+pub struct PlateMap {
+    // kept in file order so blocks and plate definitions are reproducible
+    wells: Vec<(String, WellAnnotation)>,
+    index: HashMap<String, usize>,
+}
+
+impl PlateMap {
+    /// Parses a platemap file
+    ///
+    /// `.tsv`/`.txt` files are read as tab separated, everything else as comma separated.
+    /// Well names are cleaned the same way as block definitions ("A01" => "A1").
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        columns: &PlateMapColumns,
+    ) -> Result<Self, HistDiffError> {
+        let path = path.as_ref();
+        let delimiter = match path.extension().and_then(|e| e.to_str()) {
+            Some("tsv") | Some("txt") => b'\t',
+            _ => b',',
+        };
+
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(true)
+            .flexible(true)
+            .from_reader(BufReader::new(File::open(path)?));
+
+        let headers: Vec<String> = reader.headers()?.iter().map(|h| h.to_string()).collect();
+        let position = |name: &str| headers.iter().position(|h| h == name);
+        let required = |name: &String| {
+            position(name).ok_or_else(|| HistDiffError::MissingColumn {
+                path: path.to_path_buf(),
+                column: name.clone(),
+            })
+        };
+
+        let well_idx = required(&columns.well)?;
+        let sample_idx = required(&columns.sample_type)?;
+        let compound_idx = position(&columns.compound);
+        let concentration_idx = position(&columns.concentration);
+        let block_idx = position(&columns.block);
+
+        let mut platemap = PlateMap::default();
+        for record in reader.records() {
+            let record = record?;
+            let field = |idx: Option<usize>| {
+                idx.and_then(|i| record.get(i))
+                    .map(|v| v.trim())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+            };
+
+            let Some(well) = field(Some(well_idx)) else {
+                continue;
+            };
+
+            let annotation = WellAnnotation {
+                sample_type: field(Some(sample_idx)),
+                compound: field(compound_idx),
+                concentration: field(concentration_idx).and_then(|v| v.parse::<f64>().ok()),
+                block: field(block_idx),
+            };
+            platemap.insert(well, annotation);
+        }
+
+        return Ok(platemap);
+    }
+
+    /// Adds or replaces the annotation of a well
+    pub fn insert(&mut self, well: String, annotation: WellAnnotation) {
+        let well = normalize_well(&well);
+        match self.index.get(&well) {
+            Some(&i) => self.wells[i].1 = annotation,
+            None => {
+                self.index.insert(well.clone(), self.wells.len());
+                self.wells.push((well, annotation));
+            }
+        }
+    }
+
+    /// Annotation of a well, if it is in the map
+    pub fn get(&self, well: &str) -> Option<&WellAnnotation> {
+        let well = normalize_well(well);
+        return self.index.get(&well).map(|&i| &self.wells[i].1);
+    }
+
+    /// Every well of the map in file order
+    pub fn wells(&self) -> Vec<String> {
+        return self.wells.iter().map(|(well, _)| well.clone()).collect();
+    }
+
+    /// Wells whose sample type equals `sample_type` (ignoring case)
+    pub fn wells_with_sample_type(&self, sample_type: &str) -> Vec<String> {
+        return self
+            .wells
+            .iter()
+            .filter(|(_, a)| {
+                a.sample_type
+                    .as_deref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(sample_type))
+            })
+            .map(|(well, _)| well.clone())
+            .collect();
+    }
+
+    /// Wells grouped by block in order of first appearance, `None` when no well has a block
+    pub fn blocks(&self) -> Option<Vec<Vec<String>>> {
+        let mut names: Vec<&str> = Vec::new();
+        let mut blocks: Vec<Vec<String>> = Vec::new();
+
+        for (well, annotation) in &self.wells {
+            let Some(block) = annotation.block.as_deref() else {
+                continue;
+            };
+
+            match names.iter().position(|b| *b == block) {
+                Some(i) => blocks[i].push(well.clone()),
+                None => {
+                    names.push(block);
+                    blocks.push(vec![well.clone()]);
+                }
+            }
+        }
+
+        if blocks.is_empty() {
+            return None;
+        }
+        return Some(blocks);
+    }
+
+    /// Adds `sample_type`, `compound`, `concentration` and `block` columns to a score table
+    ///
+    /// Wells are taken from the `well` column when present (batch results), else from `id`.
+    pub fn annotate(&self, df: &mut DataFrame) -> Result<(), HistDiffError> {
+        let key = if df.column("well").is_ok() {
+            "well"
+        } else {
+            "id"
+        };
+        let insert_at = df
+            .get_column_names()
+            .iter()
+            .position(|name| name.as_str() == key)
+            .map_or(0, |i| i + 1);
+
+        let annotations: Vec<Option<&WellAnnotation>> = df
+            .column(key)?
+            .str()?
+            .iter()
+            .map(|well| well.and_then(|w| self.get(w)))
+            .collect();
+
+        let sample_types: Vec<Option<String>> = annotations
+            .iter()
+            .map(|a| a.and_then(|a| a.sample_type.clone()))
+            .collect();
+        let compounds: Vec<Option<String>> = annotations
+            .iter()
+            .map(|a| a.and_then(|a| a.compound.clone()))
+            .collect();
+        let concentrations: Vec<Option<f64>> = annotations
+            .iter()
+            .map(|a| a.and_then(|a| a.concentration))
+            .collect();
+        let blocks: Vec<Option<String>> = annotations
+            .iter()
+            .map(|a| a.and_then(|a| a.block.clone()))
+            .collect();
+
+        df.insert_column(insert_at, Column::new("sample_type".into(), sample_types))?;
+        df.insert_column(insert_at + 1, Column::new("compound".into(), compounds))?;
+        df.insert_column(
+            insert_at + 2,
+            Column::new("concentration".into(), concentrations),
+        )?;
+        df.insert_column(insert_at + 3, Column::new("block".into(), blocks))?;
+
+        return Ok(());
+    }
+}
+
+impl UserConfig {
+    /// Creates a UserConfig whose wells, vehicles and blocks come from a platemap
+    ///
+    /// - plate definition => every well of the platemap
+    /// - vehicle controls => wells whose sample type is `vehicle_type` (e.g. "DMSO")
+    /// - blocks => the block column, or the whole plate when it is absent
+    ///
+    /// The platemap is kept on the config so the output is annotated with it.
+    pub fn from_platemap<P: AsRef<Path>>(
+        path: P,
+        platemap: PlateMap,
+        vehicle_type: &str,
+        id_cols: Vec<String>,
+        useless_cols: Option<Vec<String>>,
+        verbose: bool,
+        nbins: Option<usize>,
+    ) -> Self {
+        let vehicles = platemap.wells_with_sample_type(vehicle_type);
+        let mut config = UserConfig::new(
+            path,
+            id_cols,
+            useless_cols,
+            verbose,
+            platemap.blocks(),
+            Some(platemap.wells()),
+            vehicles,
+            nbins,
+        );
+        config.platemap = Some(platemap);
+
+        return config;
+    }
+}
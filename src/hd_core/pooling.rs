@@ -0,0 +1,114 @@
+use super::{error::HistDiffError, histograms::Hist1D, standardize::median};
+
+/// How the vehicle histograms of a block are combined into the control histogram
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PoolingMethod {
+    /// Adds up the cells of every vehicle (the original HistDiff pooling)
+    #[default]
+    Sum,
+    /// Per-bin median of the normalized vehicle histograms
+    Median,
+    /// Per-bin mean of the normalized vehicle histograms without the `trim` fraction
+    /// of lowest and highest values
+    TrimmedMean { trim: f64 },
+}
+
+impl PoolingMethod {
+    /// Pools the raw histograms of some vehicles, `None` when there are none
+    ///
+    /// Robust pools are rescaled to the summed cell count, so resampling and the
+    /// cell count factor see a pool of the same size as `Sum`. Fails when the
+    /// histograms do not share their bins (see `Hist1D::add`).
+    pub fn pool(&self, hists: &[&Hist1D]) -> Result<Option<Hist1D>, HistDiffError> {
+        let Some((first, rest)) = hists.split_first() else {
+            return Ok(None);
+        };
+        let mut sum = (*first).clone();
+        for hist in rest {
+            sum.add(hist)?;
+        }
+
+        let shapes: Vec<Vec<f64>> = hists
+            .iter()
+            .filter_map(|hist| {
+                let total: f64 = hist.counts.iter().sum();
+                (total > 0.0).then(|| hist.counts.iter().map(|c| c / total).collect())
+            })
+            .collect();
+        let per_bin = |bin: usize| -> Vec<f64> { shapes.iter().map(|shape| shape[bin]).collect() };
+
+        let robust: Vec<f64> = match *self {
+            PoolingMethod::Sum => return Ok(Some(sum)),
+            PoolingMethod::Median => (0..sum.nbins)
+                .map(|bin| median(&per_bin(bin)).unwrap_or(0.0))
+                .collect(),
+            PoolingMethod::TrimmedMean { trim } => (0..sum.nbins)
+                .map(|bin| trimmed_mean(&per_bin(bin), trim))
+                .collect(),
+        };
+
+        // disjoint vehicles can leave nothing to pool
+        let robust_total: f64 = robust.iter().sum();
+        if robust_total > 0.0 {
+            let total: f64 = sum.counts.iter().sum();
+            sum.counts = robust.iter().map(|c| c / robust_total * total).collect();
+        }
+        return Ok(Some(sum));
+    }
+}
+
+/// Control pooling and automatic rejection of outlier vehicle wells
+///
+/// With `reject_above`, every vehicle is first scored against the pool of the other vehicles
+/// of its block (leave-one-out). A vehicle whose median absolute score over the non-constant
+/// features, under the first of `UserConfig.metrics`, is above the threshold is left out of
+/// the pool and scored like any other well.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PoolingOptions {
+    pub method: PoolingMethod,
+    pub reject_above: Option<f64>,
+}
+
+impl PoolingOptions {
+    /// Checks the trim fraction and the rejection threshold
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        if let PoolingMethod::TrimmedMean { trim } = self.method {
+            if !(0.0..0.5).contains(&trim) {
+                return Err(HistDiffError::InvalidConfig(format!(
+                    "pooling trim must be in [0, 0.5), got {}",
+                    trim
+                )));
+            }
+        }
+        if let Some(threshold) = self.reject_above {
+            if !(threshold > 0.0 && threshold.is_finite()) {
+                return Err(HistDiffError::InvalidConfig(format!(
+                    "vehicle rejection threshold must be positive, got {}",
+                    threshold
+                )));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// A vehicle well left out of its block's control pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedVehicle {
+    pub well: String,
+    /// median absolute leave-one-out score over the non-constant features
+    pub score: f64,
+}
+
+/// Mean of `values` without the `trim` fraction of lowest and highest values
+fn trimmed_mean(values: &[f64], trim: f64) -> f64 {
+    let mut sorted: Vec<f64> = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let cut = (sorted.len() as f64 * trim).floor() as usize;
+    let kept = &sorted[cut..sorted.len() - cut];
+    if kept.is_empty() {
+        return 0.0;
+    }
+    return kept.iter().sum::<f64>() / kept.len() as f64;
+}
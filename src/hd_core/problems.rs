@@ -0,0 +1,93 @@
+use std::fmt;
+
+/// What is wrong with a feature, judged on the cells its range is computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeatureProblem {
+    /// no finite values at all
+    AllNan,
+    /// fewer finite values than `ProblemPolicy.min_finite`
+    TooFewFinite,
+    /// every finite value is the same
+    Constant,
+}
+
+impl fmt::Display for FeatureProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureProblem::AllNan => write!(f, "all values are NaN"),
+            FeatureProblem::TooFewFinite => write!(f, "too few finite values"),
+            FeatureProblem::Constant => write!(f, "constant"),
+        }
+    }
+}
+
+/// What happens to a feature with a given problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemAction {
+    /// Leaves the feature out of the histograms and scores
+    Drop,
+    /// Scores the feature anyway
+    Keep,
+    /// Fails the run with `HistDiffError::ProblematicFeature`
+    Error,
+}
+
+/// Action per problem category
+///
+/// The defaults match the original behaviour: all-NaN features are dropped, everything else is kept.
+/// Kept all-NaN features get the range `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProblemPolicy {
+    pub all_nan: ProblemAction,
+    pub too_few_finite: ProblemAction,
+    pub constant: ProblemAction,
+    /// features with fewer finite values than this count as `TooFewFinite`
+    pub min_finite: u64,
+}
+
+impl Default for ProblemPolicy {
+    fn default() -> Self {
+        ProblemPolicy {
+            all_nan: ProblemAction::Drop,
+            too_few_finite: ProblemAction::Keep,
+            constant: ProblemAction::Keep,
+            min_finite: 2,
+        }
+    }
+}
+
+impl ProblemPolicy {
+    /// Action for a problem category
+    pub fn action(&self, problem: FeatureProblem) -> ProblemAction {
+        match problem {
+            FeatureProblem::AllNan => self.all_nan,
+            FeatureProblem::TooFewFinite => self.too_few_finite,
+            FeatureProblem::Constant => self.constant,
+        }
+    }
+
+    /// Problem of a feature from its finite value count and raw extrema, `None` when it has none
+    ///
+    /// Categories are exclusive and checked in the order all-NaN, too few finite, constant.
+    pub(crate) fn classify(&self, finite: u64, low: f64, high: f64) -> Option<FeatureProblem> {
+        if finite == 0 {
+            return Some(FeatureProblem::AllNan);
+        } else if finite < self.min_finite {
+            return Some(FeatureProblem::TooFewFinite);
+        } else if low == high {
+            return Some(FeatureProblem::Constant);
+        }
+        return None;
+    }
+}
+
+/// A feature flagged while computing the ranges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProblematicFeature {
+    pub feature: String,
+    pub problem: FeatureProblem,
+    /// number of finite values seen
+    pub finite: u64,
+    /// `Drop` or `Keep`, as picked by the `ProblemPolicy`
+    pub action: ProblemAction,
+}
@@ -0,0 +1,559 @@
+use core::f64;
+use flate2::read::MultiGzDecoder;
+use polars::prelude::*;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use super::{
+    error::HistDiffError,
+    selection::{is_numeric_text, ExcludedColumn, ExclusionReason},
+    transform::{resolve_transforms, Transform},
+    utils::{resolve_columns, IdMode, UserConfig},
+    well::{normalize_well, WellId},
+};
+
+/// Number of cell rows handed to the HistDiff passes at a time
+const BATCH_ROWS: usize = 65_536;
+
+/// File formats accepted for the cell-level data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    /// Picks the format from the file extension, falling back to delimited text
+    #[default]
+    Auto,
+    /// Delimited text (tab separated by default)
+    Text,
+    /// Apache Parquet
+    Parquet,
+    /// Arrow IPC (feather v2)
+    Ipc,
+}
+
+impl InputFormat {
+    /// Resolves `Auto` against the extension of `path`
+    ///
+    /// `.parquet`/`.pq` map to Parquet and `.arrow`/`.ipc`/`.feather` map to Arrow IPC.
+    pub fn resolve(self, path: &Path) -> InputFormat {
+        if self != InputFormat::Auto {
+            return self;
+        }
+
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        return match ext.as_deref() {
+            Some("parquet") | Some("pq") => InputFormat::Parquet,
+            Some("arrow") | Some("ipc") | Some("feather") => InputFormat::Ipc,
+            _ => InputFormat::Text,
+        };
+    }
+}
+
+/// Compression of delimited text inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// `.gz` is read as gzip, `.zst`/`.zstd` as zstd, anything else as plain text
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Resolves `Auto` against the extension of `path`
+    pub fn resolve(self, path: &Path) -> Compression {
+        if self != Compression::Auto {
+            return self;
+        }
+
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        return match ext.as_deref() {
+            Some("gz") | Some("gzip") => Compression::Gzip,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        };
+    }
+}
+
+/// How delimited text inputs are parsed
+///
+/// The defaults read an uncompressed, tab separated file with a header row.
+#[derive(Debug, Clone)]
+pub struct TextOptions {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// lines starting with this byte are skipped
+    pub comment: Option<u8>,
+    /// number of raw lines thrown away before the header (vendor preambles)
+    pub skip_lines: usize,
+    /// when false, columns are named `column_1`, `column_2`, ...
+    pub has_headers: bool,
+    pub compression: Compression,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            delimiter: b'\t',
+            quote: b'"',
+            comment: None,
+            skip_lines: 0,
+            has_headers: true,
+            compression: Compression::Auto,
+        }
+    }
+}
+
+/// Opens `path` as a (possibly decompressed) byte stream positioned after the preamble
+fn open_text(path: &Path, options: &TextOptions) -> Result<Box<dyn Read>, HistDiffError> {
+    let file = File::open(path)?;
+    let mut reader: Box<dyn BufRead> = match options.compression.resolve(path) {
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        Compression::Auto | Compression::None => Box::new(BufReader::new(file)),
+    };
+
+    let mut line: Vec<u8> = Vec::new();
+    for _ in 0..options.skip_lines {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+    }
+
+    return Ok(reader);
+}
+
+/// Where the cell-level data of a HistDiff run comes from
+#[derive(Clone)]
+pub(crate) enum CellSource {
+    /// The file at `UserConfig.path`
+    Path,
+    /// A lazy polars query, collected in row slices; `UserConfig.path` is ignored
+    Frame(Box<LazyFrame>),
+    /// A polars `DataFrame` already in memory; `UserConfig.path` is ignored
+    Data(Box<DataFrame>),
+}
+
+impl CellSource {
+    /// Starts a new pass over the cell data
+    pub fn open(&self, config: &UserConfig) -> Result<CellReader, HistDiffError> {
+        match self {
+            CellSource::Path => CellReader::open(config),
+            CellSource::Frame(frame) => CellReader::from_lazy(config, (**frame).clone(), false),
+            CellSource::Data(df) => CellReader::from_lazy(config, df.as_ref().clone().lazy(), true),
+        }
+    }
+}
+
+/// A chunk of cell rows
+///
+/// `wells[r]` is the normalized ID of row `r` (see `well_name`) and `columns[j][r]` its value for feature `j`.
+/// Values that are missing or not numeric are `NaN`.
+pub(crate) struct CellBatch {
+    pub wells: Vec<String>,
+    pub columns: Vec<Vec<f64>>,
+}
+
+/// Streams the cell-level data behind a `UserConfig` as `CellBatch`es
+///
+/// Only the ID and feature columns are decoded for columnar inputs.
+/// Feature values come out already transformed (see `UserConfig.transforms`).
+pub(crate) struct CellReader {
+    pub features: Vec<String>,
+    /// non-ID columns that are not features, and why
+    pub excluded: Vec<ExcludedColumn>,
+    id_mode: IdMode,
+    /// transform of every feature, indexed like `features`
+    transforms: Vec<Option<Transform>>,
+    source: Source,
+}
+
+enum Source {
+    Text {
+        reader: csv::Reader<Box<dyn Read>>,
+        headers_len: usize,
+        id_idx: Vec<usize>,
+        feature_idx: Vec<usize>,
+    },
+    Frame {
+        /// projected query, collected `BATCH_ROWS` rows at a time
+        frame: Box<LazyFrame>,
+        /// result of `frame` for in-memory inputs, sliced instead of re-running the query
+        collected: Option<DataFrame>,
+        n_ids: usize,
+        offset: usize,
+    },
+}
+
+impl CellReader {
+    /// Opens `config.path` according to `config.input_format`
+    pub fn open(config: &UserConfig) -> Result<Self, HistDiffError> {
+        match config.input_format.resolve(&config.path) {
+            InputFormat::Parquet => {
+                let path = PlRefPath::try_from_path(&config.path)?;
+                let frame = LazyFrame::scan_parquet(path, ScanArgsParquet::default())?;
+                return CellReader::from_lazy(config, frame, false);
+            }
+            InputFormat::Ipc => {
+                let path = PlRefPath::try_from_path(&config.path)?;
+                let frame = LazyFrame::scan_ipc(
+                    path,
+                    IpcScanOptions::default(),
+                    UnifiedScanArgs::default(),
+                )?;
+                return CellReader::from_lazy(config, frame, false);
+            }
+            InputFormat::Auto | InputFormat::Text => {}
+        }
+
+        let options = &config.text_options;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .quote(options.quote)
+            .comment(options.comment)
+            .has_headers(options.has_headers)
+            .flexible(true)
+            .from_reader(open_text(&config.path, options)?);
+
+        let headers: Vec<String> = if options.has_headers {
+            reader.headers()?.iter().map(|h| h.to_string()).collect()
+        } else {
+            // without a header row the first record only tells us the width
+            (1..=reader.headers()?.len())
+                .map(|i| format!("column_{}", i))
+                .collect()
+        };
+        let (id_idx, mut feature_idx, mut excluded) = resolve_columns(config, &headers)?;
+        if config.selection.infer_types {
+            let non_numeric = sample_text_types(config, headers.len(), &feature_idx)?;
+            drop_non_numeric(&headers, &mut feature_idx, &mut excluded, non_numeric);
+        }
+
+        let features: Vec<String> = feature_idx.iter().map(|&i| headers[i].clone()).collect();
+        return Ok(CellReader {
+            transforms: resolve_transforms(&config.transforms, &features)?,
+            features,
+            excluded,
+            id_mode: config.id_mode,
+            source: Source::Text {
+                reader,
+                headers_len: headers.len(),
+                id_idx,
+                feature_idx,
+            },
+        });
+    }
+
+    /// Wraps a polars query, projecting it down to the ID and feature columns
+    ///
+    /// ID columns are read as strings and feature columns are cast to `f64`;
+    /// values that fail the cast become `NaN`. Queries are collected one row slice at a
+    /// time; `in_memory` frames are projected once and sliced instead.
+    pub fn from_lazy(
+        config: &UserConfig,
+        frame: LazyFrame,
+        in_memory: bool,
+    ) -> Result<Self, HistDiffError> {
+        let mut frame = frame;
+        let schema = frame.collect_schema()?;
+        let headers: Vec<String> = schema.iter_names().map(|name| name.to_string()).collect();
+        let (id_idx, mut feature_idx, mut excluded) = resolve_columns(config, &headers)?;
+        if config.selection.infer_types {
+            let non_numeric = sample_frame_types(config, &frame, &schema, &headers, &feature_idx)?;
+            drop_non_numeric(&headers, &mut feature_idx, &mut excluded, non_numeric);
+        }
+
+        let mut projection: Vec<Expr> = id_idx
+            .iter()
+            .map(|&i| col(headers[i].as_str()).cast(DataType::String))
+            .collect();
+        projection.extend(
+            feature_idx
+                .iter()
+                .map(|&i| col(headers[i].as_str()).cast(DataType::Float64)),
+        );
+
+        let frame = frame.select(projection);
+        let collected = match in_memory {
+            true => Some(frame.clone().collect()?),
+            false => None,
+        };
+
+        let features: Vec<String> = feature_idx.iter().map(|&i| headers[i].clone()).collect();
+        return Ok(CellReader {
+            transforms: resolve_transforms(&config.transforms, &features)?,
+            features,
+            excluded,
+            id_mode: config.id_mode,
+            source: Source::Frame {
+                frame: Box::new(frame),
+                collected,
+                n_ids: id_idx.len(),
+                offset: 0,
+            },
+        });
+    }
+
+    /// Reads the next chunk of rows, `None` once the input is exhausted
+    pub fn next_batch(&mut self) -> Result<Option<CellBatch>, HistDiffError> {
+        let Some(mut batch) = self.read_batch()? else {
+            return Ok(None);
+        };
+
+        let transforms = &self.transforms;
+        batch
+            .columns
+            .par_iter_mut()
+            .zip(transforms.par_iter())
+            .for_each(|(column, transform)| {
+                if let Some(transform) = transform {
+                    column.iter_mut().for_each(|v| *v = transform.apply(*v));
+                }
+            });
+
+        return Ok(Some(batch));
+    }
+
+    /// Reads the next chunk of rows as they are in the input
+    fn read_batch(&mut self) -> Result<Option<CellBatch>, HistDiffError> {
+        let id_mode = self.id_mode;
+        match &mut self.source {
+            Source::Text {
+                reader,
+                headers_len,
+                id_idx,
+                feature_idx,
+            } => {
+                let mut wells: Vec<String> = Vec::with_capacity(BATCH_ROWS);
+                let mut columns: Vec<Vec<f64>> =
+                    vec![Vec::with_capacity(BATCH_ROWS); feature_idx.len()];
+                let mut record = csv::StringRecord::new();
+
+                while wells.len() < BATCH_ROWS && reader.read_record(&mut record)? {
+                    if record.len() != *headers_len {
+                        continue;
+                    }
+
+                    let parts: Vec<&str> = id_idx.iter().map(|&i| &record[i]).collect();
+                    wells.push(well_name(&parts, id_mode));
+
+                    for (column, &i) in columns.iter_mut().zip(feature_idx.iter()) {
+                        column.push(record[i].trim().parse::<f64>().unwrap_or(f64::NAN));
+                    }
+                }
+
+                if wells.is_empty() {
+                    return Ok(None);
+                }
+
+                return Ok(Some(CellBatch { wells, columns }));
+            }
+            Source::Frame {
+                frame,
+                collected,
+                n_ids,
+                offset,
+            } => {
+                let df = match collected {
+                    Some(all) => all.slice(*offset as i64, BATCH_ROWS),
+                    None => (**frame)
+                        .clone()
+                        .slice(*offset as i64, BATCH_ROWS as IdxSize)
+                        .collect()?,
+                };
+                if df.height() == 0 {
+                    return Ok(None);
+                }
+                *offset += df.height();
+
+                return Ok(Some(frame_to_batch(&df, *n_ids, id_mode)?));
+            }
+        }
+    }
+}
+
+/// First non-numeric value of every feature column within the sampled prefix of a text input
+fn sample_text_types(
+    config: &UserConfig,
+    headers_len: usize,
+    feature_idx: &[usize],
+) -> Result<HashMap<usize, String>, HistDiffError> {
+    let options = &config.text_options;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .comment(options.comment)
+        .has_headers(options.has_headers)
+        .flexible(true)
+        .from_reader(open_text(&config.path, options)?);
+
+    let mut non_numeric: HashMap<usize, String> = HashMap::new();
+    let mut record = csv::StringRecord::new();
+    let mut sampled = 0;
+    while sampled < config.selection.sample_rows && reader.read_record(&mut record)? {
+        if record.len() != headers_len {
+            continue;
+        }
+        sampled += 1;
+
+        for &i in feature_idx {
+            if !non_numeric.contains_key(&i) && !is_numeric_text(&record[i]) {
+                non_numeric.insert(i, record[i].to_string());
+            }
+        }
+    }
+
+    return Ok(non_numeric);
+}
+
+/// Non-numeric feature columns of a polars query
+///
+/// Numeric, boolean and all-null columns pass, string columns are sampled like text
+/// inputs and every other type (dates, lists, ...) is reported by its type name.
+fn sample_frame_types(
+    config: &UserConfig,
+    frame: &LazyFrame,
+    schema: &Schema,
+    headers: &[String],
+    feature_idx: &[usize],
+) -> Result<HashMap<usize, String>, HistDiffError> {
+    let mut non_numeric: HashMap<usize, String> = HashMap::new();
+    let mut strings: Vec<usize> = Vec::new();
+    for &i in feature_idx {
+        match schema.get(headers[i].as_str()) {
+            Some(dtype) if dtype.is_string() => strings.push(i),
+            Some(dtype) if !(dtype.is_numeric() || dtype.is_bool() || dtype.is_null()) => {
+                non_numeric.insert(i, dtype.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    if strings.is_empty() {
+        return Ok(non_numeric);
+    }
+
+    let sample = frame
+        .clone()
+        .select(
+            strings
+                .iter()
+                .map(|&i| col(headers[i].as_str()))
+                .collect::<Vec<_>>(),
+        )
+        .slice(0, config.selection.sample_rows as IdxSize)
+        .collect()?;
+
+    for (&i, column) in strings.iter().zip(sample.columns()) {
+        if let Some(value) = column
+            .str()?
+            .iter()
+            .flatten()
+            .find(|value| !is_numeric_text(value))
+        {
+            non_numeric.insert(i, value.to_string());
+        }
+    }
+
+    return Ok(non_numeric);
+}
+
+/// Moves the non-numeric columns from the features to the excluded columns
+fn drop_non_numeric(
+    headers: &[String],
+    feature_idx: &mut Vec<usize>,
+    excluded: &mut Vec<ExcludedColumn>,
+    mut non_numeric: HashMap<usize, String>,
+) {
+    feature_idx.retain(|i| match non_numeric.remove(i) {
+        Some(value) => {
+            excluded.push(ExcludedColumn {
+                column: headers[*i].clone(),
+                reason: ExclusionReason::NonNumeric { value },
+            });
+            false
+        }
+        None => true,
+    });
+}
+
+/// Converts a projected frame (ID columns first, then features) into a `CellBatch`
+fn frame_to_batch(
+    df: &DataFrame,
+    n_ids: usize,
+    id_mode: IdMode,
+) -> Result<CellBatch, HistDiffError> {
+    let cols = df.columns();
+
+    let ids = cols[..n_ids]
+        .iter()
+        .map(|c| c.str())
+        .collect::<Result<Vec<_>, PolarsError>>()?;
+    let wells: Vec<String> = (0..df.height())
+        .map(|r| {
+            let parts: Vec<&str> = ids.iter().map(|id| id.get(r).unwrap_or("")).collect();
+            well_name(&parts, id_mode)
+        })
+        .collect();
+
+    let columns = cols[n_ids..]
+        .iter()
+        .map(|c| {
+            let values = c.f64()?;
+            Ok(values.iter().map(|v| v.unwrap_or(f64::NAN)).collect())
+        })
+        .collect::<Result<Vec<Vec<f64>>, PolarsError>>()?;
+
+    return Ok(CellBatch { wells, columns });
+}
+
+/// Builds the well ID of a row from its ID column values
+///
+/// In case there are multiple id columns they are joined with `_`;
+/// the result goes through the same normalization as the config wells ("A01" => "A1").
+/// In `IdMode::RowColumn` the numeric row and column are composed into a well name instead,
+/// falling back to the joined values when they are not valid positions.
+fn well_name(parts: &[&str], id_mode: IdMode) -> String {
+    if let (IdMode::RowColumn { zero_based }, [row, col]) = (id_mode, parts) {
+        let offset = if zero_based { 1 } else { 0 };
+        if let (Some(row), Some(col)) = (parse_index(row), parse_index(col)) {
+            if row + offset > 0 && col + offset > 0 {
+                return WellId::new(row + offset, col + offset).canonical();
+            }
+        }
+    }
+
+    return match parts {
+        [single] => normalize_well(single),
+        _ => normalize_well(&parts.join("_")),
+    };
+}
+
+/// Parses a row/column index, accepting integral floats ("3.0") from cast numeric columns
+fn parse_index(value: &str) -> Option<u32> {
+    let value = value.trim();
+    if let Ok(index) = value.parse::<u32>() {
+        return Some(index);
+    }
+
+    let index = value.parse::<f64>().ok()?;
+    if index.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&index) {
+        return Some(index as u32);
+    }
+    return None;
+}
@@ -0,0 +1,116 @@
+use regex::Regex;
+use std::fmt;
+
+use super::error::HistDiffError;
+
+/// Matches feature columns by exact name or regex
+#[derive(Debug, Clone)]
+pub enum FeatureSelector {
+    /// Exactly this feature
+    Name(String),
+    /// Every feature the regex matches (anywhere in the name, anchor it for full matches)
+    Pattern(Regex),
+}
+
+impl FeatureSelector {
+    /// Selects the column called `name`
+    pub fn name(name: &str) -> Self {
+        FeatureSelector::Name(name.to_string())
+    }
+
+    /// Selects every column matching `pattern`
+    pub fn pattern(pattern: &str) -> Result<Self, HistDiffError> {
+        let regex = Regex::new(pattern).map_err(|err| {
+            HistDiffError::InvalidConfig(format!("invalid feature pattern '{}': {}", pattern, err))
+        })?;
+        return Ok(FeatureSelector::Pattern(regex));
+    }
+
+    pub fn matches(&self, feature: &str) -> bool {
+        match self {
+            FeatureSelector::Name(name) => name == feature,
+            FeatureSelector::Pattern(pattern) => pattern.is_match(feature),
+        }
+    }
+}
+
+impl fmt::Display for FeatureSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureSelector::Name(name) => write!(f, "{}", name),
+            FeatureSelector::Pattern(pattern) => write!(f, "/{}/", pattern.as_str()),
+        }
+    }
+}
+
+/// Which of the non-ID columns are scored as features
+///
+/// A column is a feature when it is not in `UserConfig.useless_cols`, matches one of
+/// `include` (if any are given), matches none of `exclude` and, with `infer_types`, holds
+/// only numbers or missing values in the first `sample_rows` rows.
+#[derive(Debug, Clone)]
+pub struct FeatureSelection {
+    pub include: Vec<FeatureSelector>,
+    pub exclude: Vec<FeatureSelector>,
+    pub infer_types: bool,
+    pub sample_rows: usize,
+}
+
+impl Default for FeatureSelection {
+    fn default() -> Self {
+        FeatureSelection {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            infer_types: true,
+            sample_rows: 1000,
+        }
+    }
+}
+
+impl FeatureSelection {
+    /// Why `column` is not a feature, `None` when it is one (types aside)
+    pub(crate) fn exclusion(&self, column: &str) -> Option<ExclusionReason> {
+        if !self.include.is_empty() && !self.include.iter().any(|s| s.matches(column)) {
+            return Some(ExclusionReason::NotIncluded);
+        }
+
+        return self.exclude.iter().find(|s| s.matches(column)).map(|s| {
+            ExclusionReason::Excluded {
+                selector: s.to_string(),
+            }
+        });
+    }
+}
+
+/// Why a column was not scored as a feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExclusionReason {
+    /// listed in `UserConfig.useless_cols`
+    Useless,
+    /// matched none of `FeatureSelection.include`
+    NotIncluded,
+    /// matched this entry of `FeatureSelection.exclude`
+    Excluded { selector: String },
+    /// the first non-numeric value in the sampled rows, or the column type of columnar inputs
+    NonNumeric { value: String },
+}
+
+/// A non-ID column that was left out of the features
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcludedColumn {
+    pub column: String,
+    pub reason: ExclusionReason,
+}
+
+/// Whether a text cell is a number or a missing value marker
+pub(crate) fn is_numeric_text(value: &str) -> bool {
+    let value = value.trim();
+    if value.parse::<f64>().is_ok() {
+        return true;
+    }
+
+    return matches!(
+        value.to_ascii_lowercase().as_str(),
+        "" | "na" | "n/a" | "#n/a" | "null" | "none"
+    );
+}
@@ -0,0 +1,223 @@
+use rand::{rngs::Xoshiro256PlusPlus, RngExt, SeedableRng};
+
+use super::error::HistDiffError;
+
+/// How the null distribution of a well's score is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignificanceMethod {
+    /// Shuffles the cells of the well and the pooled vehicles, then splits them
+    /// back into a well and a pool of the original sizes
+    #[default]
+    Permutation,
+    /// Draws a well of the same size from the pooled vehicle cells with replacement
+    /// and scores it against the pool
+    Bootstrap,
+}
+
+/// Options of the optional p-value stage
+///
+/// Resampling works on the binned cells, so each draw is smoothed, normalized and scored
+/// exactly like the observed histograms. Every well and feature gets its own random
+/// stream derived from `seed`, so results do not depend on the number of threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignificanceOptions {
+    pub method: SignificanceMethod,
+    /// null draws per well and feature
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+impl Default for SignificanceOptions {
+    fn default() -> Self {
+        SignificanceOptions {
+            method: SignificanceMethod::default(),
+            iterations: 1000,
+            seed: 0,
+        }
+    }
+}
+
+impl SignificanceOptions {
+    /// Checks that at least one null draw is made
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        if self.iterations == 0 {
+            return Err(HistDiffError::InvalidConfig(
+                "significance needs at least 1 iteration".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+/// Draws null (well, pool) bin counts for one well and feature
+///
+/// Works on bin counts only, so a draw costs O(cells in the well * log nbins) plus
+/// O(nbins) for the pool, however many cells the pool holds.
+pub(crate) struct Resampler {
+    method: SignificanceMethod,
+    rng: Xoshiro256PlusPlus,
+    /// cells per bin of the pool (and the well, for permutations)
+    totals: Vec<u64>,
+    /// `totals` as a tree to find the bin of the n-th cell
+    tree: CountTree,
+    /// cells in the well
+    size: usize,
+    pool: Vec<f64>,
+}
+
+impl Resampler {
+    /// # params:
+    /// - exp => raw bin counts of the well
+    /// - pool => raw bin counts of the pooled vehicles
+    /// - stream => key of the random stream, e.g. well and feature
+    pub fn new(options: &SignificanceOptions, exp: &[f64], pool: &[f64], stream: &str) -> Self {
+        let totals: Vec<u64> = match options.method {
+            SignificanceMethod::Permutation => pool
+                .iter()
+                .zip(exp)
+                .map(|(p, e)| p.round() as u64 + e.round() as u64)
+                .collect(),
+            SignificanceMethod::Bootstrap => pool.iter().map(|p| p.round() as u64).collect(),
+        };
+
+        return Resampler {
+            method: options.method,
+            rng: Xoshiro256PlusPlus::seed_from_u64(stream_seed(options.seed, stream)),
+            tree: CountTree::new(&totals),
+            totals,
+            size: exp.iter().map(|c| c.round() as usize).sum(),
+            pool: pool.to_vec(),
+        };
+    }
+
+    /// Whether there are cells on both sides to resample
+    pub fn is_empty(&self) -> bool {
+        let cells: u64 = self.totals.iter().sum();
+        let pool_cells = match self.method {
+            SignificanceMethod::Permutation => cells - self.size as u64,
+            SignificanceMethod::Bootstrap => cells,
+        };
+        return self.size == 0 || pool_cells == 0;
+    }
+
+    /// One null draw as (well counts, pool counts)
+    pub fn draw(&mut self) -> (Vec<f64>, Vec<f64>) {
+        let nbins = self.totals.len();
+        let mut exp = vec![0.0; nbins];
+        let cells: u64 = self.totals.iter().sum();
+
+        match self.method {
+            SignificanceMethod::Permutation => {
+                // `size` cells drawn without replacement become the well, the rest the pool
+                let mut remaining = self.tree.clone();
+                for drawn in 0..self.size as u64 {
+                    let bin = remaining.find(self.rng.random_range(0..cells - drawn));
+                    remaining.remove(bin);
+                    exp[bin] += 1.0;
+                }
+
+                let pool = self
+                    .totals
+                    .iter()
+                    .zip(&exp)
+                    .map(|(&total, e)| total as f64 - e)
+                    .collect();
+                return (exp, pool);
+            }
+            SignificanceMethod::Bootstrap => {
+                for _ in 0..self.size {
+                    exp[self.tree.find(self.rng.random_range(0..cells))] += 1.0;
+                }
+                return (exp, self.pool.clone());
+            }
+        }
+    }
+}
+
+/// Fenwick tree over the cells per bin
+#[derive(Clone)]
+struct CountTree {
+    /// 1-based partial sums
+    tree: Vec<u64>,
+}
+
+impl CountTree {
+    fn new(counts: &[u64]) -> Self {
+        let n = counts.len();
+        let mut tree = vec![0; n + 1];
+        tree[1..].copy_from_slice(counts);
+        for i in 1..=n {
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= n {
+                tree[parent] += tree[i];
+            }
+        }
+        return CountTree { tree };
+    }
+
+    /// Bin of the `n`-th cell (0-based), counting cells bin by bin
+    fn find(&self, mut n: u64) -> usize {
+        let len = self.tree.len() - 1;
+        let mut pos = 0;
+        let mut step = len.checked_ilog2().map_or(0, |log| 1 << log);
+        while step > 0 {
+            if pos + step <= len && self.tree[pos + step] <= n {
+                pos += step;
+                n -= self.tree[pos];
+            }
+            step >>= 1;
+        }
+        return pos;
+    }
+
+    /// Takes one cell out of `bin`
+    fn remove(&mut self, bin: usize) {
+        let mut i = bin + 1;
+        while i < self.tree.len() {
+            self.tree[i] -= 1;
+            i += i & i.wrapping_neg();
+        }
+    }
+}
+
+/// Seed of one random stream, FNV-1a of `stream` mixed with the user seed
+fn stream_seed(seed: u64, stream: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325 ^ seed;
+    for byte in stream.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+/// Two-sided resampling p-value, `(1 + #{|null| >= |observed|}) / (1 + draws)`
+///
+/// `NaN` when the observed score is `NaN` or there are no draws.
+pub fn empirical_p_value(observed: f64, null: &[f64]) -> f64 {
+    if observed.is_nan() || null.is_empty() {
+        return f64::NAN;
+    }
+
+    let extreme = null.iter().filter(|s| s.abs() >= observed.abs()).count();
+    return (1 + extreme) as f64 / (1 + null.len()) as f64;
+}
+
+/// Benjamini–Hochberg q-values of a family of p-values
+///
+/// `NaN` p-values are left out of the family and get a `NaN` q-value.
+pub fn benjamini_hochberg(p_values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..p_values.len())
+        .filter(|&i| !p_values[i].is_nan())
+        .collect();
+    order.sort_by(|&a, &b| p_values[a].total_cmp(&p_values[b]));
+
+    let m = order.len() as f64;
+    let mut q_values = vec![f64::NAN; p_values.len()];
+    let mut running_min: f64 = 1.0;
+    for (rank, &i) in order.iter().enumerate().rev() {
+        running_min = running_min.min(p_values[i] * m / (rank + 1) as f64);
+        q_values[i] = running_min;
+    }
+
+    return q_values;
+}
@@ -0,0 +1,114 @@
+/// Streaming quantile sketch (KLL) used for percentile ranges
+///
+/// Keeps `O(k)` values per feature no matter how many cells are read. Inputs
+/// shorter than `k` are kept exactly; beyond that quantiles are approximate with a
+/// rank error of roughly `1.7 / k`. Compaction is deterministic so repeated runs
+/// over the same data give the same ranges.
+#[derive(Debug, Clone)]
+pub(crate) struct QuantileSketch {
+    k: usize,
+    /// items of level `i` stand for `2^i` input values
+    levels: Vec<Vec<f64>>,
+    /// alternates which half of a compacted level is kept
+    keep_odd: bool,
+}
+
+/// Default sketch size, ~0.2% rank error
+pub(crate) const SKETCH_K: usize = 1024;
+
+/// Capacity shrink factor between a level and the one above it
+const LEVEL_DECAY: f64 = 2.0 / 3.0;
+
+/// Smallest capacity of any level
+const MIN_LEVEL_CAPACITY: usize = 8;
+
+impl QuantileSketch {
+    pub fn new(k: usize) -> Self {
+        QuantileSketch {
+            k: k.max(MIN_LEVEL_CAPACITY),
+            levels: vec![Vec::new()],
+            keep_odd: false,
+        }
+    }
+
+    /// Adds a value; non-finite values are ignored
+    pub fn insert(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.levels[0].push(value);
+        if self.levels[0].len() >= self.capacity(0) {
+            self.compress();
+        }
+    }
+
+    /// Folds another sketch into this one
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        while self.levels.len() < other.levels.len() {
+            self.levels.push(Vec::new());
+        }
+        for (level, items) in other.levels.iter().enumerate() {
+            self.levels[level].extend_from_slice(items);
+        }
+        self.compress();
+    }
+
+    /// Value at quantile `q` in [0, 1], `None` when nothing was inserted
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let mut weighted: Vec<(f64, u64)> = self
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, items)| items.iter().map(move |&v| (v, 1u64 << level)))
+            .collect();
+        if weighted.is_empty() {
+            return None;
+        }
+        weighted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let total: u64 = weighted.iter().map(|(_, w)| w).sum();
+        let target = (q.clamp(0.0, 1.0) * total as f64).ceil().max(1.0) as u64;
+
+        let mut seen: u64 = 0;
+        for &(value, weight) in &weighted {
+            seen += weight;
+            if seen >= target {
+                return Some(value);
+            }
+        }
+        return weighted.last().map(|(v, _)| *v);
+    }
+
+    fn capacity(&self, level: usize) -> usize {
+        let depth = (self.levels.len() - 1 - level) as i32;
+        ((self.k as f64) * LEVEL_DECAY.powi(depth)).ceil() as usize
+    }
+
+    /// Compacts full levels until every level fits its capacity
+    fn compress(&mut self) {
+        let mut level = 0;
+        while level < self.levels.len() {
+            if self.levels[level].len() >= self.capacity(level).max(MIN_LEVEL_CAPACITY) {
+                if level + 1 == self.levels.len() {
+                    self.levels.push(Vec::new());
+                }
+
+                let mut items = std::mem::take(&mut self.levels[level]);
+                items.sort_by(|a, b| a.total_cmp(b));
+
+                // an odd item out stays on its level so no weight is lost
+                if items.len() % 2 == 1 {
+                    let last = items.pop().unwrap_or_default();
+                    self.levels[level].push(last);
+                }
+
+                let offset = if self.keep_odd { 1 } else { 0 };
+                self.keep_odd = !self.keep_odd;
+                let promoted = items.into_iter().skip(offset).step_by(2);
+                self.levels[level + 1].extend(promoted);
+            }
+            level += 1;
+        }
+    }
+}
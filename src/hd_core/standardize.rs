@@ -0,0 +1,42 @@
+/// Scales the median absolute deviation to the standard deviation of a normal distribution
+pub const MAD_SCALE: f64 = 1.4826;
+
+/// Median of the finite values, `None` when there are none
+pub fn median(values: &[f64]) -> Option<f64> {
+    let mut finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    if finite.is_empty() {
+        return None;
+    }
+    finite.sort_by(f64::total_cmp);
+
+    let mid = finite.len() / 2;
+    return match finite.len() % 2 {
+        0 => Some((finite[mid - 1] + finite[mid]) / 2.0),
+        _ => Some(finite[mid]),
+    };
+}
+
+/// Robust z-scores of `scores` against a null distribution
+///
+/// `(score - median(null)) / (1.4826 * MAD(null))`. With a MAD of 0 a score equal to the
+/// median gets 0 and any other score `NaN`; an empty null gives `NaN` everywhere.
+pub fn robust_z_scores(scores: &[f64], null: &[f64]) -> Vec<f64> {
+    let Some(center) = median(null) else {
+        return vec![f64::NAN; scores.len()];
+    };
+    let deviations: Vec<f64> = null.iter().map(|v| (v - center).abs()).collect();
+    let scale = MAD_SCALE * median(&deviations).unwrap_or(0.0);
+
+    return scores
+        .iter()
+        .map(|&score| {
+            if scale > 0.0 {
+                (score - center) / scale
+            } else if score == center {
+                0.0
+            } else {
+                f64::NAN
+            }
+        })
+        .collect();
+}
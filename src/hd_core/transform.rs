@@ -0,0 +1,110 @@
+use super::{error::HistDiffError, selection::FeatureSelector};
+
+/// Value transform applied to a feature before its ranges and histograms are computed
+///
+/// Values outside the domain of a transform (e.g. negative values for `Sqrt`) become `NaN`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    /// `ln(1 + x)`
+    Log1p,
+    /// `log10(x + offset)`
+    Log10 { offset: f64 },
+    /// `asinh(x / cofactor)`, as used for cytometry intensities
+    Arcsinh { cofactor: f64 },
+    /// `sqrt(x)`
+    Sqrt,
+    /// `(x^lambda - 1) / lambda`, `ln(x)` for a lambda of 0; only defined for positive values
+    BoxCox { lambda: f64 },
+}
+
+impl Transform {
+    /// Transforms a single value
+    pub fn apply(&self, value: f64) -> f64 {
+        let transformed = match *self {
+            Transform::Log1p => value.ln_1p(),
+            Transform::Log10 { offset } => (value + offset).log10(),
+            Transform::Arcsinh { cofactor } => (value / cofactor).asinh(),
+            Transform::Sqrt => value.sqrt(),
+            Transform::BoxCox { lambda } => {
+                if value <= 0.0 {
+                    f64::NAN
+                } else if lambda == 0.0 {
+                    value.ln()
+                } else {
+                    (value.powf(lambda) - 1.0) / lambda
+                }
+            }
+        };
+
+        // keeps infinities of finite inputs (log of 0) out of the underflow/overflow counts
+        if value.is_finite() && !transformed.is_finite() {
+            return f64::NAN;
+        }
+        return transformed;
+    }
+
+    /// Checks the transform parameters
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        let invalid = |msg: String| Err(HistDiffError::InvalidConfig(msg));
+        match *self {
+            Transform::Log10 { offset } if !offset.is_finite() => {
+                invalid(format!("log10 offset must be finite, got {}", offset))
+            }
+            Transform::Arcsinh { cofactor } if !(cofactor > 0.0 && cofactor.is_finite()) => {
+                invalid(format!(
+                    "arcsinh cofactor must be positive, got {}",
+                    cofactor
+                ))
+            }
+            Transform::BoxCox { lambda } if !lambda.is_finite() => {
+                invalid(format!("box-cox lambda must be finite, got {}", lambda))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A transform and the features it applies to
+#[derive(Debug, Clone)]
+pub struct FeatureTransform {
+    pub selector: FeatureSelector,
+    pub transform: Transform,
+}
+
+impl FeatureTransform {
+    /// Applies `transform` to the feature called `name`
+    pub fn named(name: &str, transform: Transform) -> Self {
+        FeatureTransform {
+            selector: FeatureSelector::name(name),
+            transform,
+        }
+    }
+
+    /// Applies `transform` to every feature matching `pattern`
+    pub fn matching(pattern: &str, transform: Transform) -> Result<Self, HistDiffError> {
+        return Ok(FeatureTransform {
+            selector: FeatureSelector::pattern(pattern)?,
+            transform,
+        });
+    }
+}
+
+/// Transform of every feature, the first matching entry of `transforms` wins
+pub(crate) fn resolve_transforms(
+    transforms: &[FeatureTransform],
+    features: &[String],
+) -> Result<Vec<Option<Transform>>, HistDiffError> {
+    for entry in transforms {
+        entry.transform.validate()?;
+    }
+
+    return Ok(features
+        .iter()
+        .map(|feat| {
+            transforms
+                .iter()
+                .find(|entry| entry.selector.matches(feat))
+                .map(|entry| entry.transform)
+        })
+        .collect());
+}
@@ -3,8 +3,88 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use super::{
+    binning::BinOptions,
+    calculations::RangeOptions,
+    cells::CellCountOptions,
+    error::HistDiffError,
+    histograms::Smoothing,
+    metrics::MetricSpec,
+    platemap::PlateMap,
+    pooling::PoolingOptions,
+    problems::ProblemPolicy,
+    reader::{InputFormat, TextOptions},
+    selection::{ExcludedColumn, ExclusionReason, FeatureSelection},
+    significance::SignificanceOptions,
+    transform::FeatureTransform,
+    well::{normalize_well, PlateLayout},
+};
+
+/// Controls how many times the cell data file is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMode {
+    /// Reads the file once for the min/max ranges and again to fill the histograms
+    #[default]
+    TwoPass,
+    /// Reads the file once, buffering each well's feature values as `f32` columns
+    /// in memory and binning them after the final ranges are known.
+    ///
+    /// Needs roughly `4 bytes * cells * features` of memory. Ranges are identical
+    /// to `TwoPass`; only values within `f32` precision (~1e-7 relative) of a bin
+    /// edge can land in a neighbouring bin, so scores agree with `TwoPass` to
+    /// within ~1e-6 in practice.
+    SinglePass,
+}
+
+/// How the ID columns of a row are turned into its well name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdMode {
+    /// Joins the `id_cols` values with `_` (a single `WellName` column is used as is)
+    #[default]
+    Joined,
+    /// `id_cols` are exactly two numeric columns, row then column, e.g. `["Row", "Column"]`
+    ///
+    /// Row 3, column 12 becomes "C12" (1-based) or "D13" (`zero_based`).
+    RowColumn { zero_based: bool },
+}
+
+/// Scaling applied to the experimental histograms in the HistDiff square difference
+///
+/// Other metrics compare the normalized histograms unscaled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreFactor {
+    /// The same factor for every well (the original HistDiff uses 1.0)
+    Constant(f64),
+    /// Per well, its cell count divided by the mean cell count of its block's vehicle wells
+    CellCountRatio,
+}
+
+impl Default for ScoreFactor {
+    fn default() -> Self {
+        ScoreFactor::Constant(1.0)
+    }
+}
+
+impl ScoreFactor {
+    /// Checks that a constant factor is positive and finite
+    pub fn validate(&self) -> Result<(), HistDiffError> {
+        match *self {
+            ScoreFactor::Constant(factor) if !(factor > 0.0 && factor.is_finite()) => Err(
+                HistDiffError::InvalidConfig(format!("factor must be positive, got {}", factor)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Represents a general user input for HD functions
 /// This is better than re-typing out each function params
+///
+/// With `standardize`, every score is also turned into a robust z-score against the
+/// leave-one-out scores of its block's vehicles, `(score - median) / (1.4826 * MAD)`;
+/// blocks with a single vehicle get `NaN`. With `leave_one_out`, vehicle wells are scored
+/// against the pool of the other vehicles of their block and their factor, `vehicle_cells`
+/// and p-values follow that reduced pool.
 #[derive(Debug, Clone)]
 pub struct UserConfig {
     pub path: PathBuf,
@@ -16,12 +96,41 @@ pub struct UserConfig {
     // wells could be all 384
     pub vehicle_cntrls: Vec<String>,
     pub nbins: usize,
+    pub read_mode: ReadMode,        // defaults to `ReadMode::TwoPass`
+    pub id_mode: IdMode,            // defaults to `IdMode::Joined`
+    pub metrics: Vec<MetricSpec>,   // scores emitted per feature, defaults to signed HistDiff
+    pub smoothing: Smoothing,       // defaults to `Smoothing::Exponential { alpha: 0.25 }`
+    pub factor: ScoreFactor,        // defaults to `ScoreFactor::Constant(1.0)`
+    pub range: RangeOptions,        // defaults to min/max over all cells
+    pub binning: BinOptions,        // defaults to `nbins` bins for every feature
+    pub qc_columns: bool,           // adds per-feature entry/underflow/overflow/NaN columns
+    pub input_format: InputFormat,  // defaults to `InputFormat::Auto`
+    pub text_options: TextOptions,  // delimiter, compression, ... of text inputs
+    pub platemap: Option<PlateMap>, // annotates the output when set
+    // per-feature value transforms applied while reading, first match wins
+    pub transforms: Vec<FeatureTransform>,
+    // which non-ID columns are features, see `FeatureSelection`
+    pub selection: FeatureSelection,
+    // drop, keep or error on all-NaN, sparse and constant features
+    pub problems: ProblemPolicy,
+    // cell count columns and the minimum cell count of a scored well
+    pub cells: CellCountOptions,
+    // resampling p-values and BH q-values next to the scores, skipped when `None`
+    pub significance: Option<SignificanceOptions>,
+    // robust z-scores against leave-one-out vehicle scores, see `HistDiffRes::z_scores`
+    pub standardize: bool,
+    // vehicle wells are scored against the pool of the other vehicles of their block
+    pub leave_one_out: bool,
+    // how vehicles are pooled into the control and which outlier vehicles are left out
+    pub pooling: PoolingOptions,
 }
 
 impl UserConfig {
     /// Creates a new UserConfig struct
     ///
-    /// Also formats the options for HistDiff params
+    /// Also formats the options for HistDiff params.
+    /// Well names in `block_def`, `plate_def` and `vehicle_cntrls` may be zero padded ("A01");
+    /// they are normalized to their canonical form ("A1").
     pub fn new<P: AsRef<Path>>(
         path: P,
         id_cols: Vec<String>,
@@ -34,21 +143,19 @@ impl UserConfig {
     ) -> Self {
         // Give a default plate definition is one is not provided
         let plate_def = match plate_def {
-            Some(def) => def,
+            Some(def) => clean_well_names(&def),
             None => plate_definition(),
         };
+        let vehicle_cntrls = clean_well_names(&vehicle_cntrls);
 
-        let nbins = match nbins {
-            Some(bins) => bins,
-            None => 20 as usize,
-        };
+        let nbins = nbins.unwrap_or(20);
 
         // format the block definitions
         let block_def = match block_def {
             Some(mut def) => {
                 let mut undefined_blocks: HashSet<String> = HashSet::new();
                 for block in &def {
-                    let cleaned = clean_well_names(&block);
+                    let cleaned = clean_well_names(block);
                     undefined_blocks.extend(cleaned);
                 }
 
@@ -58,7 +165,10 @@ impl UserConfig {
                     .filter(|well| !undefined_blocks.contains(well))
                     .collect();
 
-                def.push(undefined_blocks.into_iter().collect());
+                // only keep the leftover block when some wells are actually left over
+                if !undefined_blocks.is_empty() {
+                    def.push(undefined_blocks.into_iter().collect());
+                }
                 def
             }
             None => vec![plate_def.clone()],
@@ -73,53 +183,115 @@ impl UserConfig {
             plate_def,
             nbins,
             block_def,
+            read_mode: ReadMode::default(),
+            id_mode: IdMode::default(),
+            metrics: vec![MetricSpec::default()],
+            smoothing: Smoothing::default(),
+            factor: ScoreFactor::default(),
+            range: RangeOptions::default(),
+            binning: BinOptions::default(),
+            qc_columns: false,
+            input_format: InputFormat::default(),
+            text_options: TextOptions::default(),
+            platemap: None,
+            transforms: Vec::new(),
+            selection: FeatureSelection::default(),
+            problems: ProblemPolicy::default(),
+            cells: CellCountOptions::default(),
+            significance: None,
+            standardize: false,
+            leave_one_out: false,
+            pooling: PoolingOptions::default(),
         };
     }
 }
 
 /// Generates a default 384 Well lables
 ///
+/// See `PlateLayout::wells` for 96, 1536 and custom plates.
+///
 /// # Examples
-/// ```
-/// "A01"
+/// ```text
+/// "A1"
 /// "P24"
 /// ```
 pub fn plate_definition() -> Vec<String> {
-    const WELL_384_LETTERS: std::ops::RangeInclusive<u8> = ('A' as u8)..=('P' as u8);
-    const WELL_384_NUMBERS: std::ops::RangeInclusive<i32> = (1..=24);
-
-    let mut res: Vec<String> = Vec::new();
-
-    for letter in WELL_384_LETTERS {
-        for num in WELL_384_NUMBERS {
-            let format_string = format!("{}{}", letter as char, num);
-            res.push(format_string);
-        }
-    }
-
-    return res;
+    return PlateLayout::Wells384.wells();
 }
 
 /// Makes the wells into a standard format
 ///
-/// Turns "A01" into A1
+/// Turns "A01" into A1 and "af07" into AF7
 /// but "P24" is unaffected.
-/// This just removes the prefix digit of the well.
+/// Names that do not parse as a well are only trimmed.
 pub fn clean_well_names(well_names: &[String]) -> Vec<String> {
-    well_names
+    well_names.iter().map(|name| normalize_well(name)).collect()
+}
+
+/// Normalized set of well names, used wherever config wells are compared with the data
+pub(crate) fn well_set(well_names: &[String]) -> HashSet<String> {
+    return well_names.iter().map(|name| normalize_well(name)).collect();
+}
+
+/// id column indices, feature column indices and excluded non-ID columns of a header row
+pub(crate) type ResolvedColumns = (Vec<usize>, Vec<usize>, Vec<ExcludedColumn>);
+
+/// Resolves the ID and feature column indices for a header row
+///
+/// Columns are selected by name only (see `FeatureSelection`); type inference happens in the reader.
+///
+/// # returns:
+/// - (id column indices, feature column indices, excluded non-ID columns)
+pub(crate) fn resolve_columns(
+    config: &UserConfig,
+    headers: &[String],
+) -> Result<ResolvedColumns, HistDiffError> {
+    if matches!(config.id_mode, IdMode::RowColumn { .. }) && config.id_cols.len() != 2 {
+        return Err(HistDiffError::InvalidConfig(format!(
+            "row/column ID mode needs exactly two id columns (row, column), got {:?}",
+            config.id_cols
+        )));
+    }
+
+    let id_col_idx: Vec<usize> = config
+        .id_cols
         .iter()
-        .map(|name| {
-            if name.len() >= 2 {
-                let letter = &name[0..1];
-                let number_str = &name[1..];
-
-                match number_str.parse::<u32>() {
-                    Ok(number) => format!("{}{}", letter, number),
-                    Err(_) => name.clone(),
-                }
-            } else {
-                name.clone()
-            }
+        .map(|col| {
+            headers
+                .iter()
+                .position(|h| h == col)
+                .ok_or_else(|| HistDiffError::MissingIdColumn(col.clone()))
         })
-        .collect()
+        .collect::<Result<Vec<usize>, HistDiffError>>()?;
+
+    let useless: HashSet<&str> = config
+        .useless_cols
+        .iter()
+        .flatten()
+        .map(|col| col.as_str())
+        .collect();
+
+    let mut feature_idx: Vec<usize> = Vec::new();
+    let mut excluded: Vec<ExcludedColumn> = Vec::new();
+    for (i, header) in headers.iter().enumerate() {
+        if id_col_idx.contains(&i) {
+            continue;
+        }
+
+        let reason = if useless.contains(header.as_str()) {
+            Some(ExclusionReason::Useless)
+        } else {
+            config.selection.exclusion(header)
+        };
+
+        match reason {
+            Some(reason) => excluded.push(ExcludedColumn {
+                column: header.clone(),
+                reason,
+            }),
+            None => feature_idx.push(i),
+        }
+    }
+
+    return Ok((id_col_idx, feature_idx, excluded));
 }
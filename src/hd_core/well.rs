@@ -0,0 +1,161 @@
+use std::{fmt, str::FromStr};
+
+use super::error::HistDiffError;
+
+/// A well position on a plate
+///
+/// Rows and columns are 1-based: "A1" is `{ row: 1, col: 1 }` and 1536-well
+/// rows continue past "Z" as "AA", "AB", ... "AF".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WellId {
+    pub row: u32,
+    pub col: u32,
+}
+
+impl WellId {
+    pub fn new(row: u32, col: u32) -> Self {
+        WellId { row, col }
+    }
+
+    /// Parses a well name such as "A1", "A01", "a01" or "AF48"
+    ///
+    /// Leading/trailing whitespace and zero padding of the column are ignored.
+    pub fn parse(name: &str) -> Result<Self, HistDiffError> {
+        let name = name.trim();
+        let invalid = || HistDiffError::Parse(format!("invalid well name '{}'", name));
+
+        let split = name
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(name.len());
+        let (letters, digits) = name.split_at(split);
+
+        if letters.is_empty() || digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let mut row: u32 = 0;
+        for letter in letters.bytes() {
+            row = row
+                .checked_mul(26)
+                .and_then(|r| r.checked_add((letter.to_ascii_uppercase() - b'A' + 1) as u32))
+                .ok_or_else(invalid)?;
+        }
+
+        let col: u32 = digits.parse().map_err(|_| invalid())?;
+        if col == 0 {
+            return Err(invalid());
+        }
+
+        return Ok(WellId { row, col });
+    }
+
+    /// Row letters of the well, e.g. "A" or "AF"
+    pub fn row_label(&self) -> String {
+        let mut letters: Vec<u8> = Vec::new();
+        let mut row = self.row;
+        while row > 0 {
+            let rem = (row - 1) % 26;
+            letters.push(b'A' + rem as u8);
+            row = (row - 1) / 26;
+        }
+        letters.reverse();
+        String::from_utf8(letters).unwrap_or_default()
+    }
+
+    /// Canonical well name without zero padding, e.g. "A1"
+    pub fn canonical(&self) -> String {
+        self.to_string()
+    }
+
+    /// Zero padded well name, e.g. "A01" on 96/384 plates
+    pub fn padded(&self, width: usize) -> String {
+        format!("{}{:0width$}", self.row_label(), self.col, width = width)
+    }
+}
+
+impl fmt::Display for WellId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.row_label(), self.col)
+    }
+}
+
+impl FromStr for WellId {
+    type Err = HistDiffError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WellId::parse(s)
+    }
+}
+
+/// Geometry of a plate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlateLayout {
+    /// 8 rows (A-H) x 12 columns
+    Wells96,
+    /// 16 rows (A-P) x 24 columns
+    #[default]
+    Wells384,
+    /// 32 rows (A-AF) x 48 columns
+    Wells1536,
+    Custom {
+        rows: u32,
+        cols: u32,
+    },
+}
+
+impl PlateLayout {
+    /// Standard layout with the given number of wells, if any
+    pub fn from_well_count(wells: usize) -> Option<Self> {
+        match wells {
+            96 => Some(PlateLayout::Wells96),
+            384 => Some(PlateLayout::Wells384),
+            1536 => Some(PlateLayout::Wells1536),
+            _ => None,
+        }
+    }
+
+    pub fn rows(&self) -> u32 {
+        match self {
+            PlateLayout::Wells96 => 8,
+            PlateLayout::Wells384 => 16,
+            PlateLayout::Wells1536 => 32,
+            PlateLayout::Custom { rows, .. } => *rows,
+        }
+    }
+
+    pub fn cols(&self) -> u32 {
+        match self {
+            PlateLayout::Wells96 => 12,
+            PlateLayout::Wells384 => 24,
+            PlateLayout::Wells1536 => 48,
+            PlateLayout::Custom { cols, .. } => *cols,
+        }
+    }
+
+    pub fn contains(&self, well: &WellId) -> bool {
+        (1..=self.rows()).contains(&well.row) && (1..=self.cols()).contains(&well.col)
+    }
+
+    /// Every well of the layout in row-major order
+    pub fn well_ids(&self) -> Vec<WellId> {
+        let cols = self.cols();
+        (1..=self.rows())
+            .flat_map(|row| (1..=cols).map(move |col| WellId::new(row, col)))
+            .collect()
+    }
+
+    /// Canonical names of every well of the layout, usable as `UserConfig::plate_def`
+    pub fn wells(&self) -> Vec<String> {
+        self.well_ids().iter().map(|w| w.canonical()).collect()
+    }
+}
+
+/// Canonical form of a well name ("A01" => "A1", "af07" => "AF7")
+///
+/// Names that are not wells (e.g. joined multi-column IDs) are returned trimmed but otherwise unchanged.
+pub fn normalize_well(name: &str) -> String {
+    match WellId::parse(name) {
+        Ok(well) => well.canonical(),
+        Err(_) => name.trim().to_string(),
+    }
+}
@@ -1,7 +1,31 @@
 #![allow(unused_parens, unused_imports)]
+#![allow(clippy::needless_return, clippy::too_many_arguments)]
 mod hd;
 mod hd_core;
-pub use hd::{calculate_scores, HistDiffRes};
-pub use hd_core::calculations::get_min_max_plate;
-pub use hd_core::histograms::{hist_square_diff, hist_square_diff_deprecated, Hist1D};
-pub use hd_core::utils::UserConfig;
+pub use hd::{
+    calculate_scores, calculate_scores_batch, calculate_scores_df, calculate_scores_lazy,
+    plates_from_glob, BatchOptions, HistDiffRes, RangeScope,
+};
+pub use hd_core::binning::{BinOptions, BinRule};
+pub use hd_core::calculations::{
+    get_min_max_plate, ConstantWidening, MinMax, MinMaxPlateResult, RangeCells, RangeOptions,
+    RangeStrategy,
+};
+pub use hd_core::cells::{CellCountOptions, LowCellAction};
+pub use hd_core::error::HistDiffError;
+pub use hd_core::histograms::{
+    hist_square_diff, hist_square_diff_deprecated, Hist1D, HistCounts, OutOfRange, Smoothing,
+};
+pub use hd_core::metrics::{mean_proxy_sign, metric_scores, DistanceMetric, Metric, MetricSpec};
+pub use hd_core::platemap::{PlateMap, PlateMapColumns, WellAnnotation};
+pub use hd_core::pooling::{PoolingMethod, PoolingOptions, RejectedVehicle};
+pub use hd_core::problems::{FeatureProblem, ProblemAction, ProblemPolicy, ProblematicFeature};
+pub use hd_core::reader::{Compression, InputFormat, TextOptions};
+pub use hd_core::selection::{ExcludedColumn, ExclusionReason, FeatureSelection, FeatureSelector};
+pub use hd_core::significance::{
+    benjamini_hochberg, empirical_p_value, SignificanceMethod, SignificanceOptions,
+};
+pub use hd_core::standardize::{median, robust_z_scores, MAD_SCALE};
+pub use hd_core::transform::{FeatureTransform, Transform};
+pub use hd_core::utils::{IdMode, ReadMode, ScoreFactor, UserConfig};
+pub use hd_core::well::{normalize_well, PlateLayout, WellId};
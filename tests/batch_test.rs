@@ -0,0 +1,201 @@
+mod common;
+
+use approx::assert_relative_eq;
+use histdiff_core::{
+    calculate_scores, calculate_scores_batch, plates_from_glob, BatchOptions, HistDiffError,
+    RangeScope, UserConfig,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Writes three plates into a fresh directory; the last one has `f_a` scaled up
+fn write_campaign(name: &str) -> Vec<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("histdiff_core_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let wells = common::small_plate();
+    let mut plates = Vec::new();
+    for (i, scale) in [1.0, 1.0, 3.0].iter().enumerate() {
+        let path = dir.join(format!("plate_{}.tsv", i + 1));
+        common::write_plate_tsv_to(&path, &wells, 25, 7 + i as u64, *scale);
+        plates.push(path);
+    }
+    plates
+}
+
+fn template() -> UserConfig {
+    common::plate_config(
+        Path::new("template.tsv"),
+        None,
+        Some(common::small_plate()),
+        &["A1", "B1"],
+    )
+}
+
+#[test]
+fn test_per_plate_batch_matches_single_plates() {
+    let plates = write_campaign("batch_per_plate");
+    let res = calculate_scores_batch(&plates, &template(), &BatchOptions::default()).unwrap();
+
+    assert_eq!(
+        res.raw_scores.len(),
+        plates.len() * common::small_plate().len()
+    );
+
+    for (i, path) in plates.iter().enumerate() {
+        let mut config = template();
+        config.path = path.clone();
+        let single = calculate_scores(&config).unwrap();
+
+        for (well, feats) in &single.raw_scores {
+            let key = format!("plate_{}:{}", i + 1, well);
+            for (feat, score) in feats {
+                assert_relative_eq!(*score, res.raw_scores[&key][feat], epsilon = 1e-12);
+            }
+        }
+    }
+
+    let df = res.dataframe_scores.unwrap();
+    assert_eq!(df.height(), res.raw_scores.len());
+    let names: Vec<String> = df
+        .get_column_names()
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+    assert_eq!(&names[..3], &["id", "plate", "well"]);
+}
+
+#[test]
+fn test_campaign_ranges_differ_from_per_plate() {
+    let plates = write_campaign("batch_campaign");
+    let options = BatchOptions {
+        range_scope: RangeScope::Campaign,
+        max_parallel_plates: 2,
+    };
+
+    let per_plate = calculate_scores_batch(&plates, &template(), &BatchOptions::default()).unwrap();
+    let campaign = calculate_scores_batch(&plates, &template(), &options).unwrap();
+
+    assert_eq!(per_plate.raw_scores.len(), campaign.raw_scores.len());
+
+    // the scaled plate widens the shared f_a range, squeezing plate 1 into fewer bins
+    let differs = per_plate
+        .raw_scores
+        .iter()
+        .filter(|(key, _)| key.starts_with("plate_1:"))
+        .any(|(key, feats)| (feats["f_a"] - campaign.raw_scores[key]["f_a"]).abs() > 1e-9);
+    assert!(differs);
+}
+
+#[test]
+fn test_failing_plate_is_named() {
+    let mut plates = write_campaign("batch_failure");
+    plates.push(std::env::temp_dir().join("histdiff_core_batch_missing.tsv"));
+
+    match calculate_scores_batch(&plates, &template(), &BatchOptions::default()) {
+        Err(HistDiffError::Plate { plate, source }) => {
+            assert_eq!(plate, "histdiff_core_batch_missing");
+            assert!(matches!(*source, HistDiffError::Io(_)));
+        }
+        other => panic!(
+            "expected a plate error, got {:?}",
+            other.map(|r| r.raw_scores.len())
+        ),
+    }
+}
+
+#[test]
+fn test_campaign_plate_missing_feature_is_named() {
+    let plates = write_campaign("batch_campaign_missing");
+    let content: String = fs::read_to_string(&plates[1])
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut cols: Vec<&str> = line.split('\t').collect();
+            cols.remove(3);
+            format!("{}\n", cols.join("\t"))
+        })
+        .collect();
+    fs::write(&plates[1], content).unwrap();
+
+    let options = BatchOptions {
+        range_scope: RangeScope::Campaign,
+        ..BatchOptions::default()
+    };
+    match calculate_scores_batch(&plates, &template(), &options) {
+        Err(HistDiffError::Plate { plate, source }) => {
+            assert_eq!(plate, "plate_2");
+            assert!(matches!(*source, HistDiffError::MissingFeature(ref f) if f == "f_b"));
+        }
+        other => panic!(
+            "expected a plate error, got {:?}",
+            other.map(|r| r.raw_scores.len())
+        ),
+    }
+}
+
+#[test]
+fn test_plates_from_glob() {
+    let plates = write_campaign("batch_glob");
+    let dir = plates[0].parent().unwrap();
+
+    let found = plates_from_glob(&format!("{}/plate_*.tsv", dir.display())).unwrap();
+    assert_eq!(found, plates);
+}
+
+#[test]
+fn test_same_name_in_different_directories_kept_apart() {
+    let root = std::env::temp_dir().join("histdiff_core_batch_same_name");
+    let _ = fs::remove_dir_all(&root);
+
+    let wells = common::small_plate();
+    let mut plates = Vec::new();
+    for (i, run) in ["a", "b"].iter().enumerate() {
+        fs::create_dir_all(root.join(run)).unwrap();
+        let path = root.join(run).join("plate.tsv");
+        common::write_plate_tsv_to(&path, &wells, 25, 3 + i as u64, 1.0);
+        plates.push(path);
+    }
+
+    let res = calculate_scores_batch(&plates, &template(), &BatchOptions::default()).unwrap();
+    assert_eq!(res.raw_scores.len(), 2 * wells.len());
+    assert!(res.raw_scores.contains_key("a/plate:C2"));
+    assert!(res.raw_scores.contains_key("b/plate:C2"));
+}
+
+#[test]
+fn test_dotted_plate_names_kept_apart() {
+    let dir = std::env::temp_dir().join("histdiff_core_batch_dotted");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let wells = common::small_plate();
+    let mut plates = Vec::new();
+    for (i, name) in ["run.2024.01.tsv", "run.2024.02.tsv"].iter().enumerate() {
+        let path = dir.join(name);
+        common::write_plate_tsv_to(&path, &wells, 25, 5 + i as u64, 1.0);
+        plates.push(path);
+    }
+
+    let res = calculate_scores_batch(&plates, &template(), &BatchOptions::default()).unwrap();
+    assert_eq!(res.raw_scores.len(), 2 * wells.len());
+    assert!(res.raw_scores.contains_key("run.2024.01:C2"));
+    assert!(res.raw_scores.contains_key("run.2024.02:C2"));
+}
+
+#[test]
+fn test_duplicate_plate_is_an_error() {
+    let mut plates = write_campaign("batch_duplicate");
+    plates.push(plates[0].clone());
+
+    match calculate_scores_batch(&plates, &template(), &BatchOptions::default()) {
+        Err(HistDiffError::DuplicatePlate(plate)) => assert_eq!(plate, "plate_1"),
+        other => panic!(
+            "expected a duplicate plate error, got {:?}",
+            other.map(|r| r.raw_scores.len())
+        ),
+    }
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, calculate_scores_batch, get_min_max_plate, BatchOptions, BinRule,
+    HistDiffError, RangeCells, ReadMode,
+};
+use std::{fs, path::Path};
+
+/// `f_a` values of a synthetic plate, optionally only of the given wells
+fn f_a_values(path: &Path, wells: Option<&[&str]>) -> Vec<f64> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .filter(|cols| wells.is_none_or(|wells| wells.contains(&cols[1])))
+        .map(|cols| cols[2].parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_count_rules() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("binning_counts", &wells, 50);
+    let mut config = common::config(&path);
+
+    let fixed = get_min_max_plate(&config).unwrap();
+    assert!(fixed.nbins.iter().all(|&n| n == 20));
+
+    // 24 wells * 50 cells
+    config.binning.rule = BinRule::Sturges;
+    assert_eq!(
+        get_min_max_plate(&config).unwrap().nbins_of("f_a"),
+        Some(12)
+    );
+    config.binning.rule = BinRule::Sqrt;
+    assert_eq!(
+        get_min_max_plate(&config).unwrap().nbins_of("f_a"),
+        Some(35)
+    );
+    config.binning.max_bins = 16;
+    assert_eq!(
+        get_min_max_plate(&config).unwrap().nbins_of("f_a"),
+        Some(16)
+    );
+
+    // 4 vehicle wells * 50 cells
+    config.binning.max_bins = 512;
+    config.binning.rule = BinRule::Sturges;
+    config.binning.cells = RangeCells::Vehicles;
+    assert_eq!(get_min_max_plate(&config).unwrap().nbins_of("f_a"), Some(9));
+
+    config.binning.max_bins = 0;
+    assert!(matches!(
+        get_min_max_plate(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_width_rules() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("binning_widths", &wells, 50);
+    let mut config = common::config(&path);
+
+    let all = f_a_values(&path, None);
+    let n = all.len() as f64;
+    let (low, high) = all
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(l, h), &v| (l.min(v), h.max(v)));
+    let mean = all.iter().sum::<f64>() / n;
+    let std = (all.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    let scott = ((high - low) / (3.49 * std * n.powf(-1.0 / 3.0))).ceil() as usize;
+
+    config.binning.rule = BinRule::Scott;
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert_eq!(ranges.nbins_of("f_a"), Some(scott));
+    // no spread, falls back to Sturges
+    assert_eq!(ranges.nbins_of("f_const"), Some(12));
+
+    // vehicle cells fit in the sketch buffer so quartiles are exact
+    let mut vehicles = f_a_values(&path, Some(&["A1", "B1", "C1", "D1"]));
+    vehicles.sort_by(|a, b| a.total_cmp(b));
+    let nv = vehicles.len() as f64;
+    let at = |q: f64| vehicles[(q * nv).ceil() as usize - 1];
+    let fd = ((high - low) / (2.0 * (at(0.75) - at(0.25)) * nv.powf(-1.0 / 3.0))).ceil() as usize;
+
+    config.binning.rule = BinRule::FreedmanDiaconis;
+    config.binning.cells = RangeCells::Vehicles;
+    assert_eq!(
+        get_min_max_plate(&config).unwrap().nbins_of("f_a"),
+        Some(fd)
+    );
+}
+
+#[test]
+fn test_bins_reported_in_results() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("binning_results", &wells, 50);
+    let mut config = common::config(&path);
+    config.binning.rule = BinRule::Sqrt;
+
+    let two_pass = calculate_scores(&config).unwrap();
+    assert_eq!(two_pass.nbins["f_a"], 35);
+    assert_eq!(two_pass.nbins["f_b"], 35);
+
+    config.read_mode = ReadMode::SinglePass;
+    let single_pass = calculate_scores(&config).unwrap();
+    assert_eq!(single_pass.nbins, two_pass.nbins);
+    for (well, scores) in &two_pass.raw_scores {
+        approx::assert_abs_diff_eq!(
+            scores["f_a"],
+            single_pass.raw_scores[well]["f_a"],
+            epsilon = 1e-6
+        );
+    }
+
+    let dir = std::env::temp_dir().join("histdiff_core_binning_batch");
+    fs::create_dir_all(&dir).unwrap();
+    let plates: Vec<_> = [20, 80]
+        .iter()
+        .enumerate()
+        .map(|(i, &cells)| {
+            let path = dir.join(format!("plate_{}.tsv", i));
+            common::write_plate_tsv_to(&path, &wells, cells, i as u64, 1.0);
+            path
+        })
+        .collect();
+    let res = calculate_scores_batch(&plates, &config, &BatchOptions::default()).unwrap();
+    // 24 * 20 and 24 * 80 cells
+    assert_eq!(res.nbins["plate_0:f_a"], 22);
+    assert_eq!(res.nbins["plate_1:f_a"], 44);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, calculate_scores_batch, BatchOptions, CellCountOptions, LowCellAction,
+    ReadMode, UserConfig,
+};
+use std::{fs, path::Path};
+
+fn config(path: &Path) -> UserConfig {
+    let blocks = vec![
+        vec!["A1".into(), "A2".into(), "A3".into(), "A6".into()],
+        vec!["C1".into(), "C3".into(), "C6".into()],
+    ];
+    common::block_config(path, Some(blocks), &["A1", "B1", "C1", "D1"])
+}
+
+/// Synthetic plate with 20 cells per well, except 5 in A6 and 8 in C3
+fn write_uneven_plate(name: &str) -> std::path::PathBuf {
+    let path = common::write_plate_tsv(name, &common::small_plate(), 20);
+    let mut seen: std::collections::HashMap<String, usize> = Default::default();
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .enumerate()
+        .filter(|(i, line)| {
+            if *i == 0 {
+                return true;
+            }
+            let well = line.split('\t').nth(1).unwrap().to_string();
+            let count = seen.entry(well.clone()).or_default();
+            *count += 1;
+            match well.as_str() {
+                "A6" => *count <= 5,
+                "C3" => *count <= 8,
+                _ => true,
+            }
+        })
+        .map(|(_, line)| format!("{}\n", line))
+        .collect();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn test_cell_counts() {
+    let path = write_uneven_plate("cells_counts");
+    let mut config = config(&path);
+
+    for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+        config.read_mode = read_mode;
+        let res = calculate_scores(&config).unwrap();
+        assert_eq!(res.cell_counts["A6"], 5);
+        assert_eq!(res.cell_counts["C3"], 8);
+        assert_eq!(res.cell_counts["B2"], 20);
+        // B1 and D1 are the vehicles of the leftover block
+        assert_eq!(res.vehicle_cells["A6"], 20);
+        assert_eq!(res.vehicle_cells["C3"], 20);
+        assert_eq!(res.vehicle_cells["B2"], 40);
+        assert!(res.low_cell_wells.is_empty());
+        assert!(res.dataframe_scores.unwrap().column("cells").is_err());
+    }
+
+    config.cells.columns = true;
+    let df = calculate_scores(&config).unwrap().dataframe_scores.unwrap();
+    let names: Vec<&str> = df.get_column_names().iter().map(|n| n.as_str()).collect();
+    assert_eq!(&names[..3], &["id", "cells", "vehicle_cells"]);
+    let ids = df.column("id").unwrap().str().unwrap();
+    let cells = df.column("cells").unwrap().u64().unwrap();
+    for (id, cells) in ids.iter().zip(cells.iter()) {
+        let expected = match id.unwrap() {
+            "A6" => 5,
+            "C3" => 8,
+            _ => 20,
+        };
+        assert_eq!(cells, Some(expected));
+    }
+}
+
+#[test]
+fn test_low_cell_wells() {
+    let path = write_uneven_plate("cells_low");
+    let mut config = config(&path);
+    config.cells = CellCountOptions {
+        columns: false,
+        min_cells: 10,
+        action: LowCellAction::Flag,
+    };
+
+    let flagged = calculate_scores(&config).unwrap();
+    assert_eq!(flagged.low_cell_wells, vec!["A6", "C3"]);
+    assert!(flagged.raw_scores["A6"]["f_a"].is_finite());
+    let df = flagged.dataframe_scores.unwrap();
+    let ids = df.column("id").unwrap().str().unwrap();
+    let low = df.column("low_cells").unwrap().bool().unwrap();
+    for (id, low) in ids.iter().zip(low.iter()) {
+        assert_eq!(low, Some(matches!(id.unwrap(), "A6" | "C3")));
+    }
+
+    config.cells.action = LowCellAction::Mask;
+    let masked = calculate_scores(&config).unwrap();
+    assert_eq!(masked.low_cell_wells, flagged.low_cell_wells);
+    assert!(masked.raw_scores["A6"].values().all(|s| s.is_nan()));
+    assert!(masked.raw_scores["C3"].values().all(|s| s.is_nan()));
+    assert_eq!(masked.raw_scores["B2"], flagged.raw_scores["B2"]);
+}
+
+#[test]
+fn test_batch_cell_counts() {
+    let path = write_uneven_plate("cells_batch");
+    let mut config = config(&path);
+    config.cells.min_cells = 6;
+
+    let res = calculate_scores_batch(&[path], &config, &BatchOptions::default()).unwrap();
+    assert_eq!(res.cell_counts["histdiff_core_cells_batch:A6"], 5);
+    assert_eq!(res.vehicle_cells["histdiff_core_cells_batch:B2"], 40);
+    assert_eq!(res.low_cell_wells, vec!["histdiff_core_cells_batch:A6"]);
+}
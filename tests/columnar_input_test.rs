@@ -0,0 +1,73 @@
+mod common;
+
+use approx::assert_relative_eq;
+use histdiff_core::{calculate_scores, get_min_max_plate, InputFormat, ReadMode, UserConfig};
+use polars::prelude::*;
+use std::{fs::File, path::Path};
+
+/// Re-writes a synthetic TSV plate as Parquet and Arrow IPC
+fn write_columnar(tsv: &Path, name: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+    let mut df = common::read_plate_df(tsv);
+
+    let parquet = std::env::temp_dir().join(format!("histdiff_core_{}.parquet", name));
+    ParquetWriter::new(File::create(&parquet).unwrap())
+        .finish(&mut df)
+        .unwrap();
+
+    let ipc = std::env::temp_dir().join(format!("histdiff_core_{}.arrow", name));
+    IpcWriter::new(File::create(&ipc).unwrap())
+        .finish(&mut df)
+        .unwrap();
+
+    (parquet, ipc)
+}
+
+fn config_for(path: &Path, wells: &[String]) -> UserConfig {
+    common::plate_config(path, None, Some(wells.to_vec()), &["A1", "B1"])
+}
+
+#[test]
+fn test_input_format_from_extension() {
+    let auto = InputFormat::Auto;
+    assert_eq!(auto.resolve(Path::new("a.parquet")), InputFormat::Parquet);
+    assert_eq!(auto.resolve(Path::new("a.PQ")), InputFormat::Parquet);
+    assert_eq!(auto.resolve(Path::new("a.arrow")), InputFormat::Ipc);
+    assert_eq!(auto.resolve(Path::new("a.feather")), InputFormat::Ipc);
+    assert_eq!(auto.resolve(Path::new("a.tsv")), InputFormat::Text);
+    assert_eq!(
+        InputFormat::Parquet.resolve(Path::new("a.tsv")),
+        InputFormat::Parquet
+    );
+}
+
+#[test]
+fn test_columnar_inputs_match_tsv() {
+    let wells = common::small_plate();
+    let tsv = common::write_plate_tsv("columnar", &wells, 40);
+    let (parquet, ipc) = write_columnar(&tsv, "columnar");
+
+    let expected = calculate_scores(&config_for(&tsv, &wells)).unwrap();
+    let tsv_ranges = get_min_max_plate(&config_for(&tsv, &wells)).unwrap();
+
+    for path in [&parquet, &ipc] {
+        let ranges = get_min_max_plate(&config_for(path, &wells)).unwrap();
+        assert_eq!(ranges.features, tsv_ranges.features);
+        for ((_, a), (_, b)) in ranges.min_max.iter().zip(&tsv_ranges.min_max) {
+            assert_eq!(a.xlow, b.xlow);
+            assert_eq!(a.xhigh, b.xhigh);
+        }
+
+        for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+            let mut config = config_for(path, &wells);
+            config.read_mode = read_mode;
+            let res = calculate_scores(&config).unwrap();
+
+            assert_eq!(res.raw_scores.len(), expected.raw_scores.len());
+            for (well, feats) in &expected.raw_scores {
+                for (feat, score) in feats {
+                    assert_relative_eq!(*score, res.raw_scores[well][feat], epsilon = 1e-6);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+#![allow(dead_code)]
+use histdiff_core::UserConfig;
+use polars::prelude::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Small deterministic generator so synthetic plates are identical on every run
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        Lcg(seed)
+    }
+
+    /// uniform value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Wells A1..D6 used by the synthetic plates
+pub fn small_plate() -> Vec<String> {
+    let mut wells = Vec::new();
+    for row in ['A', 'B', 'C', 'D'] {
+        for col in 1..=6 {
+            wells.push(format!("{}{}", row, col));
+        }
+    }
+    wells
+}
+
+/// Writes a synthetic cell-by-cell TSV into the temp dir and returns its path
+///
+/// Columns: `PlateName`, `WellName`, `f_a`, `f_b`, `f_const`, `f_empty`.
+/// Wells in column 6 are shifted upwards so they score positive against the
+/// column 1 vehicles.
+pub fn write_plate_tsv(name: &str, wells: &[String], cells_per_well: usize) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("histdiff_core_{}.tsv", name));
+    write_plate_tsv_to(&path, wells, cells_per_well, 42, 1.0);
+    path
+}
+
+/// Same as `write_plate_tsv` with an explicit destination, seed and scale for `f_a`
+pub fn write_plate_tsv_to(
+    path: &Path,
+    wells: &[String],
+    cells_per_well: usize,
+    seed: u64,
+    scale: f64,
+) {
+    let mut rng = Lcg::new(seed);
+    let mut out = String::from("PlateName\tWellName\tf_a\tf_b\tf_const\tf_empty\n");
+
+    for well in wells {
+        let shift = if well.ends_with('6') { 2.0 } else { 0.0 };
+        for _ in 0..cells_per_well {
+            let a = (rng.next_f64() * 10.0 + shift) * scale;
+            let b = (rng.next_f64() * 5.0).powi(2) - shift;
+            out.push_str(&format!("plate_1\t{}\t{:.4}\t{:.4}\t3.5\tNA\n", well, a, b));
+        }
+    }
+
+    fs::write(path, out).expect("Unable to write synthetic plate");
+}
+
+pub fn useless_cols() -> Option<Vec<String>> {
+    Some(vec!["PlateName".to_string()])
+}
+
+/// Config over a synthetic plate with the column 1 wells as vehicle controls
+pub fn config(path: &Path) -> UserConfig {
+    vehicle_config(path, &["A1", "B1", "C1", "D1"])
+}
+
+/// Config over a synthetic plate with the given vehicle controls
+pub fn vehicle_config(path: &Path, vehicles: &[&str]) -> UserConfig {
+    block_config(path, None, vehicles)
+}
+
+/// Config over a synthetic plate with the given blocks and vehicle controls
+pub fn block_config(
+    path: &Path,
+    block_def: Option<Vec<Vec<String>>>,
+    vehicles: &[&str],
+) -> UserConfig {
+    plate_config(path, block_def, None, vehicles)
+}
+
+/// Config over the `plate_def` wells of a synthetic plate with the given blocks and vehicles
+pub fn plate_config(
+    path: &Path,
+    block_def: Option<Vec<Vec<String>>>,
+    plate_def: Option<Vec<String>>,
+    vehicles: &[&str],
+) -> UserConfig {
+    UserConfig::new(
+        path,
+        vec!["WellName".into()],
+        useless_cols(),
+        false,
+        block_def,
+        plate_def,
+        vehicles.iter().map(|w| w.to_string()).collect(),
+        None,
+    )
+}
+
+/// Loads a synthetic TSV plate into a polars `DataFrame`
+pub fn read_plate_df(path: &Path) -> DataFrame {
+    CsvReadOptions::default()
+        .with_parse_options(CsvParseOptions::default().with_separator(b'\t'))
+        .try_into_reader_with_file_path(Some(path.to_path_buf()))
+        .unwrap()
+        .finish()
+        .unwrap()
+}
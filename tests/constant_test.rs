@@ -0,0 +1,117 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, get_min_max_plate, ConstantWidening, HistDiffError, MinMax, RangeCells,
+    ReadMode, ScoreFactor, UserConfig,
+};
+use std::fs;
+
+fn range_of(config: &UserConfig, feature: &str) -> MinMax {
+    get_min_max_plate(config)
+        .unwrap()
+        .min_max
+        .into_iter()
+        .find(|(feat, _)| feat == feature)
+        .map(|(_, range)| range)
+        .unwrap()
+}
+
+#[test]
+fn test_symmetric_widening() {
+    let absolute = ConstantWidening::Absolute(0.5);
+    assert_eq!(
+        absolute.widen(-3.5),
+        MinMax {
+            xlow: -4.0,
+            xhigh: -3.0
+        }
+    );
+
+    let relative = ConstantWidening::Relative(0.1);
+    let negative = relative.widen(-20.0);
+    approx::assert_abs_diff_eq!(negative.xlow, -22.0, epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(negative.xhigh, -18.0, epsilon = 1e-12);
+    assert_eq!(
+        relative.widen(0.0),
+        MinMax {
+            xlow: -0.1,
+            xhigh: 0.1
+        }
+    );
+
+    // bounds stay distinct for huge constants
+    let huge = ConstantWidening::Relative(1e-300).widen(1e300);
+    assert!(huge.xlow < huge.xhigh);
+}
+
+#[test]
+fn test_constant_feature_ranges() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("constant_ranges", &wells, 20);
+    let mut config = common::config(&path);
+
+    assert_eq!(
+        range_of(&config, "f_const"),
+        MinMax {
+            xlow: 3.0,
+            xhigh: 4.0
+        }
+    );
+
+    config.range.constant = ConstantWidening::Relative(0.01);
+    let range = range_of(&config, "f_const");
+    approx::assert_abs_diff_eq!(range.xlow, 3.465, epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(range.xhigh, 3.535, epsilon = 1e-12);
+
+    config.range.constant = ConstantWidening::Absolute(0.0);
+    assert!(matches!(
+        get_min_max_plate(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_constant_features_score_zero() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("constant_scores", &wells, 20);
+    let mut config = common::config(&path);
+    // a factor other than 1 would give identical histograms a non-zero score
+    config.factor = ScoreFactor::Constant(0.5);
+
+    for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+        config.read_mode = read_mode;
+        let res = calculate_scores(&config).unwrap();
+        for scores in res.raw_scores.values() {
+            assert_eq!(scores["f_const"], 0.0);
+            assert_ne!(scores["f_a"], 0.0);
+        }
+    }
+}
+
+#[test]
+fn test_constant_in_vehicles_only() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("constant_vehicles", &wells, 20);
+    // f_b is 1.0 in the column 1 vehicles and keeps its values elsewhere
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut cols: Vec<&str> = line.split('\t').collect();
+            if cols[1].ends_with('1') {
+                cols[3] = "1.0";
+            }
+            format!("{}\n", cols.join("\t"))
+        })
+        .collect();
+    fs::write(&path, content).unwrap();
+
+    let mut config = common::config(&path);
+    config.range.cells = RangeCells::Vehicles;
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert_eq!(ranges.constant_features, vec!["f_const"]);
+
+    let res = calculate_scores(&config).unwrap();
+    assert_ne!(res.raw_scores["A6"]["f_b"], 0.0);
+    assert_eq!(res.raw_scores["A6"]["f_const"], 0.0);
+}
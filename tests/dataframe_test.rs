@@ -0,0 +1,60 @@
+mod common;
+
+use approx::assert_relative_eq;
+use histdiff_core::{
+    calculate_scores, calculate_scores_df, calculate_scores_lazy, ReadMode, UserConfig,
+};
+use polars::prelude::*;
+use std::path::Path;
+
+fn config_for(wells: &[String]) -> UserConfig {
+    // the path is never opened for in-memory inputs
+    common::plate_config(
+        Path::new("unused.tsv"),
+        None,
+        Some(wells.to_vec()),
+        &["A1", "B1"],
+    )
+}
+
+#[test]
+fn test_dataframe_matches_file() {
+    let wells = common::small_plate();
+    let tsv = common::write_plate_tsv("dataframe", &wells, 40);
+    let df = common::read_plate_df(&tsv);
+
+    let mut file_config = config_for(&wells);
+    file_config.path = tsv.clone();
+    let expected = calculate_scores(&file_config).unwrap();
+
+    for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+        let mut config = config_for(&wells);
+        config.read_mode = read_mode;
+
+        let res = calculate_scores_df(&df, &config).unwrap();
+        assert_eq!(res.raw_scores.len(), expected.raw_scores.len());
+        for (well, feats) in &expected.raw_scores {
+            for (feat, score) in feats {
+                assert_relative_eq!(*score, res.raw_scores[well][feat], epsilon = 1e-6);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_lazyframe_with_upstream_filter() {
+    let wells = common::small_plate();
+    let tsv = common::write_plate_tsv("lazyframe", &wells, 40);
+    let df = common::read_plate_df(&tsv);
+
+    let lf = df
+        .lazy()
+        .filter(col("WellName").neq(lit("C3")))
+        .with_column((col("f_a") * lit(2.0)).alias("f_a_doubled"));
+
+    let res = calculate_scores_lazy(lf, &config_for(&wells)).unwrap();
+
+    assert!(!res.raw_scores.contains_key("C3"));
+    assert_eq!(res.raw_scores.len(), wells.len() - 1);
+    assert!(res.raw_scores["A1"].contains_key("f_a_doubled"));
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use histdiff_core::{calculate_scores, get_min_max_plate, HistDiffError, UserConfig};
+
+fn config_for(name: &str, block_def: Option<Vec<Vec<String>>>) -> UserConfig {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv(name, &wells, 20);
+
+    common::plate_config(&path, block_def, Some(wells), &["A1", "B1"])
+}
+
+#[test]
+fn test_missing_id_column() {
+    let mut config = config_for("err_missing_id", None);
+    config.id_cols = vec!["Well".into()];
+
+    match get_min_max_plate(&config) {
+        Err(HistDiffError::MissingIdColumn(col)) => assert_eq!(col, "Well"),
+        other => panic!("unexpected result: {:?}", other.map(|r| r.features)),
+    }
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::MissingIdColumn(_))
+    ));
+}
+
+#[test]
+fn test_missing_file_is_io_error() {
+    let mut config = config_for("err_missing_file", None);
+    config.path = std::env::temp_dir().join("histdiff_core_does_not_exist.tsv");
+
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::Io(_))
+    ));
+}
+
+#[test]
+fn test_block_without_controls() {
+    // second block holds rows C and D, which have no vehicle wells
+    let block_1: Vec<String> = common::small_plate()
+        .into_iter()
+        .filter(|w| w.starts_with('A') || w.starts_with('B'))
+        .collect();
+    let block_2: Vec<String> = common::small_plate()
+        .into_iter()
+        .filter(|w| w.starts_with('C') || w.starts_with('D'))
+        .collect();
+
+    let config = config_for("err_no_controls", Some(vec![block_1, block_2]));
+    assert_eq!(config.block_def.len(), 2);
+
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::MissingControls { block: 1 })
+    ));
+}
+
+#[test]
+fn test_empty_block() {
+    let block_1 = common::small_plate();
+    let block_2: Vec<String> = vec!["P23".into(), "P24".into()];
+
+    let config = config_for("err_empty_block", Some(vec![block_1, block_2]));
+
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::EmptyBlock { block: 1 })
+    ));
+}
+
+#[test]
+fn test_to_csv_reports_io_error() {
+    let config = config_for("err_to_csv", None);
+    let mut res = calculate_scores(&config).expect("scoring failed");
+
+    let bad_path = std::env::temp_dir()
+        .join("histdiff_core_missing_dir")
+        .join("out.csv");
+    assert!(matches!(res.to_csv(bad_path), Err(HistDiffError::Io(_))));
+
+    let good_path = std::env::temp_dir().join("histdiff_core_err_to_csv.csv");
+    assert!(res.to_csv(&good_path).is_ok());
+    assert!(good_path.exists());
+}
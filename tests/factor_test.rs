@@ -0,0 +1,61 @@
+mod common;
+
+use histdiff_core::{calculate_scores, HistDiffError, ScoreFactor, UserConfig};
+
+fn config(name: &str) -> UserConfig {
+    let path = common::write_plate_tsv(name, &common::small_plate(), 20);
+    common::config(&path)
+}
+
+#[test]
+fn test_constant_factor() {
+    let mut config = config("factor_constant");
+
+    let default = calculate_scores(&config).unwrap();
+    assert!(default.factors.values().all(|&f| f == 1.0));
+    let df = default.dataframe_scores.unwrap();
+    assert!(df.column("factor").is_err());
+
+    config.factor = ScoreFactor::Constant(2.0);
+    let scaled = calculate_scores(&config).unwrap();
+    assert_eq!(scaled.factors.len(), 24);
+    assert!(scaled.factors.values().all(|&f| f == 2.0));
+    assert_ne!(
+        scaled.raw_scores["A6"]["f_a"],
+        default.raw_scores["A6"]["f_a"]
+    );
+
+    let df = scaled.dataframe_scores.unwrap();
+    let factors = df.column("factor").unwrap().f64().unwrap();
+    assert!(factors.iter().all(|f| f == Some(2.0)));
+    assert_eq!(df.get_column_names()[1].as_str(), "factor");
+
+    config.factor = ScoreFactor::Constant(0.0);
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_cell_count_ratio_factor() {
+    let mut config = config("factor_ratio");
+
+    // every well has 20 cells and the vehicles 20 cells each on average => 20 / 20
+    config.factor = ScoreFactor::CellCountRatio;
+    let ratio = calculate_scores(&config).unwrap();
+    assert!(ratio.factors.values().all(|&f| f == 1.0));
+
+    // pooling fewer vehicles leaves the ratio alone
+    let mut fewer = config.clone();
+    fewer.vehicle_cntrls = vec!["A1".into(), "B1".into()];
+    let fewer = calculate_scores(&fewer).unwrap();
+    assert!(fewer.factors.values().all(|&f| f == 1.0));
+
+    config.factor = ScoreFactor::Constant(1.0);
+    let constant = calculate_scores(&config).unwrap();
+    assert_eq!(ratio.raw_scores, constant.raw_scores);
+
+    let df = ratio.dataframe_scores.unwrap();
+    assert!(df.column("factor").is_ok());
+}
@@ -5,7 +5,7 @@ fn test_hist1d_creation() {
     let nbins = 20;
     let xlow = 0.0;
     let xhigh = 1.0;
-    let hist = Hist1D::new(nbins, xlow, xhigh);
+    let hist = Hist1D::new(nbins, xlow, xhigh).unwrap();
 
     assert_eq!(hist.nbins, nbins);
     assert_eq!(hist.xlow, xlow);
@@ -26,7 +26,7 @@ fn test_hist1d_creation() {
 
 #[test]
 fn test_hist1d_fill() {
-    let mut hist = Hist1D::new(5, 0.0, 1.0);
+    let mut hist = Hist1D::new(5, 0.0, 1.0).unwrap();
     let data = vec![0.1, 0.2, 0.3, 0.4, 0.5];
 
     hist.fill(&data);
@@ -39,7 +39,7 @@ fn test_hist1d_fill() {
 
 #[test]
 fn test_hist1d_fill_various_bins() {
-    let mut hist = Hist1D::new(5, 0.0, 1.0);
+    let mut hist = Hist1D::new(5, 0.0, 1.0).unwrap();
     let data = vec![
         0.0,  // Lower bound
         0.2,  // Bin 1
@@ -63,3 +63,12 @@ fn test_hist1d_fill_various_bins() {
     let expected_counts = vec![1.0, 1.0, 2.0, 0.0, 2.0];
     assert_eq!(hist.counts, expected_counts);
 }
+
+#[test]
+fn test_hist1d_rejects_invalid_ranges() {
+    assert!(Hist1D::new(0, 0.0, 1.0).is_err());
+    assert!(Hist1D::new(5, 1.0, 1.0).is_err());
+    assert!(Hist1D::new(5, 1.0, -1.0).is_err());
+    assert!(Hist1D::new(5, f64::NAN, 1.0).is_err());
+    assert!(Hist1D::new(5, 0.0, f64::INFINITY).is_err());
+}
@@ -1,5 +1,5 @@
 use approx::assert_relative_eq;
-use histdiff_core::{hist_square_diff, hist_square_diff_deprecated};
+use histdiff_core::{hist_square_diff, hist_square_diff_deprecated, HistDiffError};
 use ndarray::{arr1, arr2};
 
 #[test]
@@ -106,12 +106,30 @@ fn test_hd_sq_dff_factor() {
     }
 }
 
-// #[test]
-// fn test_hd_sq_dff_mismatched_shapes() {
-//     let exp = vec![vec![1.0, 2.0, 3.0]];
-//     let ctrl = vec![1.0, 2.0]; // Incorrect shape
-//     let factor = 1.0;
-//
-//     let result = hist_square_diff(&exp, &ctrl, factor);
-//     assert!(result.is_err());
-// }
+#[test]
+fn test_hd_sq_dff_mismatched_shapes() {
+    let exp = vec![vec![1.0, 2.0, 3.0]];
+    let ctrl = vec![1.0, 2.0]; // Incorrect shape
+    let factor = 1.0;
+
+    let result = hist_square_diff(&exp, &ctrl, factor);
+    assert!(matches!(
+        result,
+        Err(HistDiffError::ShapeMismatch {
+            expected: 2,
+            found: 3
+        })
+    ));
+
+    // ragged wells
+    let exp = vec![vec![1.0, 2.0, 3.0], vec![1.0, 2.0]];
+    let ctrl = vec![1.0, 2.0, 3.0];
+    let result = hist_square_diff(&exp, &ctrl, factor);
+    assert!(matches!(
+        result,
+        Err(HistDiffError::ShapeMismatch {
+            expected: 3,
+            found: 2
+        })
+    ));
+}
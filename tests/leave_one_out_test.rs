@@ -0,0 +1,64 @@
+mod common;
+
+use histdiff_core::{calculate_scores, ScoreFactor};
+
+fn median_abs(scores: &[f64]) -> f64 {
+    let mut scores: Vec<f64> = scores.iter().map(|s| s.abs()).collect();
+    scores.sort_by(f64::total_cmp);
+    (scores[1] + scores[2]) / 2.0
+}
+
+#[test]
+fn test_vehicles_scored_leave_one_out() {
+    let path = common::write_plate_tsv("leave_one_out", &common::small_plate(), 50);
+    let vehicles = ["A1", "B1", "C1", "D1"];
+    let mut config = common::vehicle_config(&path, &vehicles);
+
+    let pooled = calculate_scores(&config).unwrap();
+    config.leave_one_out = true;
+    let res = calculate_scores(&config).unwrap();
+
+    // A1 against the other three vehicles, experimental wells are untouched
+    let others = calculate_scores(&common::vehicle_config(&path, &["B1", "C1", "D1"])).unwrap();
+    approx::assert_abs_diff_eq!(
+        res.raw_scores["A1"]["f_a"],
+        others.raw_scores["A1"]["f_a"],
+        epsilon = 1e-12
+    );
+    assert_eq!(res.raw_scores["B3"], pooled.raw_scores["B3"]);
+    assert_eq!(res.raw_scores["A6"], pooled.raw_scores["A6"]);
+
+    // no longer pulled towards 0 by comparing against themselves
+    let vehicle_scores = |res: &histdiff_core::HistDiffRes| -> Vec<f64> {
+        vehicles.iter().map(|w| res.raw_scores[*w]["f_a"]).collect()
+    };
+    assert!(median_abs(&vehicle_scores(&res)) > median_abs(&vehicle_scores(&pooled)));
+
+    assert_eq!(res.vehicle_cells["A1"], 150);
+    assert_eq!(res.vehicle_cells["B3"], 200);
+
+    config.factor = ScoreFactor::CellCountRatio;
+    let res = calculate_scores(&config).unwrap();
+    // 50 cells against a mean of 50 cells per vehicle, with or without the well itself
+    approx::assert_abs_diff_eq!(res.factors["A1"], 1.0, epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(res.factors["B3"], 1.0, epsilon = 1e-12);
+
+    // with standardization the vehicle null is the vehicles' own scores
+    config.standardize = true;
+    let res = calculate_scores(&config).unwrap();
+    for well in vehicles {
+        assert_eq!(res.vehicle_null[well], res.raw_scores[well]);
+    }
+}
+
+#[test]
+fn test_single_vehicle_leave_one_out() {
+    let path = common::write_plate_tsv("leave_one_out_single", &common::small_plate(), 20);
+    let mut config = common::vehicle_config(&path, &["A1"]);
+    config.leave_one_out = true;
+
+    let res = calculate_scores(&config).unwrap();
+    assert!(res.raw_scores["A1"].values().all(|s| s.is_nan()));
+    assert!(res.raw_scores["A6"]["f_a"].is_finite());
+    assert_eq!(res.vehicle_cells["A1"], 0);
+}
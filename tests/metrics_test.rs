@@ -0,0 +1,103 @@
+mod common;
+
+use approx::assert_abs_diff_eq;
+use histdiff_core::{
+    calculate_scores, hist_square_diff, metric_scores, DistanceMetric, HistDiffError, Metric,
+    MetricSpec,
+};
+
+const ALL_METRICS: [Metric; 8] = [
+    Metric::HistDiff,
+    Metric::KolmogorovSmirnov,
+    Metric::Wasserstein,
+    Metric::JensenShannon,
+    Metric::KullbackLeibler,
+    Metric::Hellinger,
+    Metric::Bhattacharyya,
+    Metric::ChiSquare,
+];
+
+#[test]
+fn test_metric_values() {
+    let p = [0.1, 0.2, 0.3, 0.4];
+    for metric in ALL_METRICS {
+        assert_abs_diff_eq!(metric.distance(&p, &p), 0.0, epsilon = 1e-12);
+    }
+
+    // disjoint histograms one bin apart
+    let a = [1.0, 0.0];
+    let b = [0.0, 1.0];
+    assert_abs_diff_eq!(Metric::HistDiff.distance(&a, &b), 2.0);
+    assert_abs_diff_eq!(Metric::KolmogorovSmirnov.distance(&a, &b), 1.0);
+    assert_abs_diff_eq!(Metric::Wasserstein.distance(&a, &b), 1.0);
+    assert_abs_diff_eq!(
+        Metric::JensenShannon.distance(&a, &b),
+        std::f64::consts::LN_2,
+        epsilon = 1e-12
+    );
+    assert_abs_diff_eq!(Metric::Hellinger.distance(&a, &b), 1.0);
+    assert_eq!(Metric::Bhattacharyya.distance(&a, &b), f64::INFINITY);
+    assert_abs_diff_eq!(Metric::ChiSquare.distance(&a, &b), 2.0);
+    assert!(Metric::KullbackLeibler.distance(&a, &b).is_finite());
+
+    // earth mover's distance grows with the shift, KS does not
+    let c = [0.0, 0.0, 1.0];
+    let d = [1.0, 0.0, 0.0];
+    assert_abs_diff_eq!(Metric::Wasserstein.distance(&c, &d), 2.0);
+    assert_abs_diff_eq!(Metric::KolmogorovSmirnov.distance(&c, &d), 1.0);
+}
+
+#[test]
+fn test_signed_metric_scores() {
+    let ctrl = vec![0.25, 0.5, 0.25];
+    let exp = vec![vec![0.5, 0.5, 0.0], vec![0.0, 0.5, 0.5], ctrl.clone()];
+
+    let hd = metric_scores(&Metric::HistDiff, &exp, &ctrl, true).unwrap();
+    assert_eq!(hd, hist_square_diff(&exp, &ctrl, 1.0).unwrap());
+
+    let ks = metric_scores(&Metric::KolmogorovSmirnov, &exp, &ctrl, true).unwrap();
+    assert_abs_diff_eq!(ks[0], -0.25);
+    assert_abs_diff_eq!(ks[1], 0.25);
+    assert_abs_diff_eq!(ks[2], 0.0);
+
+    let unsigned = metric_scores(&Metric::KolmogorovSmirnov, &exp, &ctrl, false).unwrap();
+    assert!(unsigned.iter().all(|&s| s >= 0.0));
+
+    assert!(metric_scores(&Metric::Hellinger, &[vec![1.0]], &ctrl, true).is_err());
+}
+
+#[test]
+fn test_config_selects_metrics() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("metrics_select", &wells, 40);
+
+    let mut config = common::config(&path);
+    let default_scores = calculate_scores(&config).unwrap().raw_scores;
+
+    config.metrics = vec![
+        MetricSpec::default(),
+        MetricSpec::signed(Metric::KolmogorovSmirnov),
+        MetricSpec::unsigned(Metric::Wasserstein),
+    ];
+    let res = calculate_scores(&config).unwrap();
+
+    for (well, scores) in &res.raw_scores {
+        assert_eq!(scores["f_a"], default_scores[well]["f_a"]);
+        assert_eq!(scores["f_a"].signum(), scores["f_a_ks"].signum());
+        assert!(scores["f_a_wasserstein_unsigned"] >= 0.0);
+        if well.ends_with('6') {
+            assert!(scores["f_a_ks"] > 0.0);
+        }
+    }
+
+    let df = res.dataframe_scores.unwrap();
+    for column in ["f_a", "f_a_ks", "f_a_wasserstein_unsigned", "f_b_ks"] {
+        assert!(df.column(column).is_ok(), "missing column {}", column);
+    }
+
+    config.metrics.clear();
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use histdiff_core::{calculate_scores, HistDiffError, PlateMap, PlateMapColumns, UserConfig};
+use std::{fs, path::PathBuf};
+
+/// Column 1 holds DMSO vehicles, rows A-B form block "top" and rows C-D block "bottom"
+fn write_platemap(name: &str) -> PathBuf {
+    let mut out = String::from("384_Well,sample_type,compound,concentration,block\n");
+    for row in ['A', 'B', 'C', 'D'] {
+        for col in 1..=6 {
+            let block = if row < 'C' { "top" } else { "bottom" };
+            let (sample_type, compound, conc) = if col == 1 {
+                ("DMSO", "", "")
+            } else {
+                ("compound", "cmpd_x", "10.0")
+            };
+            out.push_str(&format!(
+                "{}{:02},{},{},{},{}\n",
+                row, col, sample_type, compound, conc, block
+            ));
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("histdiff_core_{}.csv", name));
+    fs::write(&path, out).unwrap();
+    path
+}
+
+#[test]
+fn test_platemap_parsing() {
+    let path = write_platemap("platemap_parse");
+    let platemap = PlateMap::from_path(&path, &PlateMapColumns::default()).unwrap();
+
+    assert_eq!(platemap.wells().len(), 24);
+    assert_eq!(
+        platemap.wells_with_sample_type("dmso"),
+        vec!["A1", "B1", "C1", "D1"]
+    );
+
+    let c3 = platemap.get("C03").unwrap();
+    assert_eq!(c3.compound.as_deref(), Some("cmpd_x"));
+    assert_eq!(c3.concentration, Some(10.0));
+    assert_eq!(c3.block.as_deref(), Some("bottom"));
+    assert_eq!(platemap.get("A1").unwrap().compound, None);
+
+    let blocks = platemap.blocks().unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert!(blocks[0]
+        .iter()
+        .all(|w| w.starts_with('A') || w.starts_with('B')));
+
+    let columns = PlateMapColumns {
+        sample_type: "SampleType".to_string(),
+        ..PlateMapColumns::default()
+    };
+    match PlateMap::from_path(&path, &columns) {
+        Err(HistDiffError::MissingColumn {
+            path: missing,
+            column,
+        }) => {
+            assert_eq!(missing, path);
+            assert_eq!(column, "SampleType");
+        }
+        other => panic!("expected a missing column error, got {:?}", other.is_ok()),
+    }
+}
+
+#[test]
+fn test_config_from_platemap_annotates_output() {
+    let wells = common::small_plate();
+    let data = common::write_plate_tsv("platemap_scores", &wells, 20);
+    let platemap = PlateMap::from_path(
+        write_platemap("platemap_scores"),
+        &PlateMapColumns::default(),
+    )
+    .unwrap();
+
+    let config = UserConfig::from_platemap(
+        &data,
+        platemap,
+        "DMSO",
+        vec!["WellName".into()],
+        common::useless_cols(),
+        false,
+        None,
+    );
+    assert_eq!(config.vehicle_cntrls, vec!["A1", "B1", "C1", "D1"]);
+    assert_eq!(config.block_def.len(), 2);
+    assert_eq!(config.plate_def.len(), 24);
+
+    let res = calculate_scores(&config).unwrap();
+    let df = res.dataframe_scores.unwrap();
+    assert_eq!(df.height(), 24);
+
+    let names: Vec<String> = df
+        .get_column_names()
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+    assert_eq!(
+        &names[..5],
+        &["id", "sample_type", "compound", "concentration", "block"]
+    );
+
+    let ids = df.column("id").unwrap().str().unwrap();
+    let blocks = df.column("block").unwrap().str().unwrap();
+    let concentrations = df.column("concentration").unwrap().f64().unwrap();
+    for i in 0..df.height() {
+        let id = ids.get(i).unwrap();
+        let expected = if id.starts_with('A') || id.starts_with('B') {
+            "top"
+        } else {
+            "bottom"
+        };
+        assert_eq!(blocks.get(i), Some(expected));
+        assert_eq!(concentrations.get(i).is_some(), !id.ends_with('1'));
+    }
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, calculate_scores_batch, BatchOptions, Hist1D, HistDiffError, PoolingMethod,
+    PoolingOptions,
+};
+use std::fs;
+
+// the shifted B1 has a median |leave-one-out score| around 0.045, the other vehicles around 0.01
+const REJECT_ABOVE: f64 = 0.03;
+
+fn hist(values: &[f64]) -> Hist1D {
+    let mut hist = Hist1D::new(2, 0.0, 1.0).unwrap();
+    hist.fill(values);
+    hist
+}
+
+/// Synthetic plate where the vehicle B1 looks like a treated well
+fn write_plate_with_bad_vehicle(name: &str) -> std::path::PathBuf {
+    let path = common::write_plate_tsv(name, &common::small_plate(), 50);
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut fields: Vec<String> = line.split('\t').map(|f| f.to_string()).collect();
+            if fields[1] == "B1" {
+                let a: f64 = fields[2].parse().unwrap();
+                fields[2] = format!("{:.4}", a + 4.0);
+            }
+            format!("{}\n", fields.join("\t"))
+        })
+        .collect();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn test_pooling_methods() {
+    let hists = [
+        hist(&[0.2, 0.2, 0.7, 0.7]),
+        hist(&[0.2, 0.7, 0.7, 0.7]),
+        hist(&[0.2, 0.7, 0.7, 0.7, 0.7, 0.7, 0.7, 0.7]),
+    ];
+    let refs: Vec<&Hist1D> = hists.iter().collect();
+
+    let sum = PoolingMethod::Sum.pool(&refs).unwrap().unwrap();
+    assert_eq!(sum.counts, vec![4.0, 12.0]);
+    assert_eq!(sum.entries(), 16);
+
+    // per-bin median of the shapes [.5, .5], [.25, .75], [.125, .875], scaled to 16 cells
+    let median = PoolingMethod::Median.pool(&refs).unwrap().unwrap();
+    assert_eq!(median.counts, vec![4.0, 12.0]);
+
+    let mean = PoolingMethod::TrimmedMean { trim: 0.0 }
+        .pool(&refs)
+        .unwrap()
+        .unwrap();
+    approx::assert_abs_diff_eq!(mean.counts[0], 0.875 / 3.0 * 16.0, epsilon = 1e-12);
+    let trimmed = PoolingMethod::TrimmedMean { trim: 0.34 }
+        .pool(&refs)
+        .unwrap()
+        .unwrap();
+    assert_eq!(trimmed.counts, median.counts);
+
+    assert!(PoolingMethod::Median.pool(&[]).unwrap().is_none());
+
+    let wider = Hist1D::new(3, 0.0, 1.0).unwrap();
+    assert!(matches!(
+        PoolingMethod::Sum.pool(&[&hists[0], &wider]),
+        Err(HistDiffError::ShapeMismatch {
+            expected: 2,
+            found: 3
+        })
+    ));
+    let shifted = Hist1D::new(2, 0.0, 2.0).unwrap();
+    assert!(matches!(
+        PoolingMethod::Sum.pool(&[&hists[0], &shifted]),
+        Err(HistDiffError::RangeMismatch {
+            expected: (0.0, 1.0),
+            found: (0.0, 2.0)
+        })
+    ));
+}
+
+#[test]
+fn test_robust_pooling_scores() {
+    let path = write_plate_with_bad_vehicle("pooling_methods");
+    let mut config = common::config(&path);
+
+    let summed = calculate_scores(&config).unwrap();
+    config.pooling.method = PoolingMethod::Median;
+    let median = calculate_scores(&config).unwrap();
+    assert!(median.rejected_vehicles.is_empty());
+
+    // the bad vehicle drags the summed control towards the treated wells
+    assert!(median.raw_scores["A6"]["f_a"] > summed.raw_scores["A6"]["f_a"]);
+    assert_eq!(median.raw_scores["A6"]["f_const"], 0.0);
+
+    config.pooling.method = PoolingMethod::TrimmedMean { trim: 0.5 };
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_outlier_vehicles_are_rejected() {
+    let path = write_plate_with_bad_vehicle("pooling_reject");
+    let mut config = common::config(&path);
+    config.pooling = PoolingOptions {
+        method: PoolingMethod::Sum,
+        reject_above: Some(REJECT_ABOVE),
+    };
+
+    let res = calculate_scores(&config).unwrap();
+    let rejected: Vec<&str> = res
+        .rejected_vehicles
+        .iter()
+        .map(|r| r.well.as_str())
+        .collect();
+    assert_eq!(rejected, vec!["B1"]);
+    assert!(res.rejected_vehicles[0].score > REJECT_ABOVE);
+
+    // same as never listing B1 as a vehicle, but B1 is still scored
+    let mut without_b1 = config.clone();
+    without_b1.vehicle_cntrls = vec!["A1".into(), "C1".into(), "D1".into()];
+    without_b1.pooling = PoolingOptions::default();
+    let expected = calculate_scores(&without_b1).unwrap();
+    assert_eq!(res.raw_scores, expected.raw_scores);
+    assert!(res.raw_scores["B1"]["f_a"] > 0.0);
+    assert_eq!(res.vehicle_cells["A6"], 150);
+
+    let batch = calculate_scores_batch(&[path], &config, &BatchOptions::default()).unwrap();
+    assert_eq!(
+        batch.rejected_vehicles[0].well,
+        "histdiff_core_pooling_reject:B1"
+    );
+
+    // a threshold nothing passes leaves the block without controls
+    config.pooling.reject_above = Some(1e-12);
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::MissingControls { block: 0 })
+    ));
+}
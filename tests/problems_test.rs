@@ -0,0 +1,154 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, get_min_max_plate, FeatureProblem, HistDiffError, MinMax, ProblemAction,
+    ProblematicFeature, RangeStrategy, ReadMode,
+};
+use std::fs;
+
+/// Synthetic plate where `f_b` only has values in its first `finite` rows
+fn write_sparse_plate(name: &str, finite: usize) -> std::path::PathBuf {
+    let path = common::write_plate_tsv(name, &common::small_plate(), 20);
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut cols: Vec<&str> = line.split('\t').collect();
+            if i > finite {
+                cols[3] = "NA";
+            }
+            format!("{}\n", cols.join("\t"))
+        })
+        .collect();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+/// Synthetic plate of 12000 cells where `f_b` is 0 in every cell but one
+fn write_spot_plate(name: &str) -> std::path::PathBuf {
+    let path = common::write_plate_tsv(name, &common::small_plate(), 500);
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut cols: Vec<&str> = line.split('\t').collect();
+            match i {
+                0 => {}
+                1 => cols[3] = "7",
+                _ => cols[3] = "0",
+            }
+            format!("{}\n", cols.join("\t"))
+        })
+        .collect();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn problem_of<'a>(
+    problems: &'a [ProblematicFeature],
+    feature: &str,
+) -> Option<&'a ProblematicFeature> {
+    problems.iter().find(|p| p.feature == feature)
+}
+
+#[test]
+fn test_default_report() {
+    let path = write_sparse_plate("problems_default", 1);
+    let config = common::config(&path);
+
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert_eq!(ranges.features, vec!["f_a", "f_b", "f_const"]);
+
+    let problems = &ranges.problematic_features;
+    assert_eq!(problems.len(), 3);
+    assert_eq!(
+        problem_of(problems, "f_empty"),
+        Some(&ProblematicFeature {
+            feature: "f_empty".to_string(),
+            problem: FeatureProblem::AllNan,
+            finite: 0,
+            action: ProblemAction::Drop,
+        })
+    );
+    assert_eq!(
+        problem_of(problems, "f_b").map(|p| (p.problem, p.finite, p.action)),
+        Some((FeatureProblem::TooFewFinite, 1, ProblemAction::Keep))
+    );
+    assert_eq!(
+        problem_of(problems, "f_const").map(|p| (p.problem, p.finite)),
+        Some((FeatureProblem::Constant, 480))
+    );
+
+    // the report reaches the scores in both read modes
+    for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+        let mut config = config.clone();
+        config.read_mode = read_mode;
+        let res = calculate_scores(&config).unwrap();
+        assert_eq!(&res.problematic_features, problems);
+    }
+}
+
+#[test]
+fn test_policies() {
+    let path = write_sparse_plate("problems_policies", 5);
+    let mut config = common::config(&path);
+    config.problems.min_finite = 10;
+    config.problems.too_few_finite = ProblemAction::Drop;
+    config.problems.constant = ProblemAction::Drop;
+    config.problems.all_nan = ProblemAction::Keep;
+
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert_eq!(ranges.features, vec!["f_a", "f_empty"]);
+    assert_eq!(
+        ranges.min_max[1].1,
+        MinMax {
+            xlow: 0.0,
+            xhigh: 1.0
+        }
+    );
+
+    let res = calculate_scores(&config).unwrap();
+    assert!(res.raw_scores["A6"].contains_key("f_empty"));
+    assert!(!res.raw_scores["A6"].contains_key("f_b"));
+
+    config.problems.min_finite = 2;
+    config.problems.all_nan = ProblemAction::Error;
+    match calculate_scores(&config) {
+        Err(HistDiffError::ProblematicFeature { feature, problem }) => {
+            assert_eq!(feature, "f_empty");
+            assert_eq!(problem, FeatureProblem::AllNan);
+        }
+        other => panic!(
+            "expected a problematic feature error, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
+
+#[test]
+fn test_classified_before_percentile_ranges() {
+    let path = write_spot_plate("problems_spot");
+    let mut config = common::config(&path);
+    config.range.strategy = RangeStrategy::Percentile {
+        low: 0.1,
+        high: 99.9,
+    };
+    config.problems.constant = ProblemAction::Drop;
+
+    for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+        config.read_mode = read_mode;
+        let ranges = get_min_max_plate(&config).unwrap();
+        assert!(ranges.features.contains(&"f_b".to_string()));
+        assert!(problem_of(&ranges.problematic_features, "f_b").is_none());
+        assert!(!ranges.constant_features.contains(&"f_b".to_string()));
+
+        let res = calculate_scores(&config).unwrap();
+        assert!(res.raw_scores["A6"].contains_key("f_b"));
+        assert_eq!(
+            problem_of(&res.problematic_features, "f_const").map(|p| p.action),
+            Some(ProblemAction::Drop)
+        );
+    }
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use histdiff_core::{calculate_scores, Hist1D, HistCounts, UserConfig};
+use std::fs;
+
+#[test]
+fn test_hist_counts() {
+    let mut hist = Hist1D::new(2, 0.0, 1.0).unwrap();
+    hist.fill(&[
+        0.2,
+        0.7,
+        1.0,
+        -0.1,
+        1.5,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+    ]);
+
+    assert_eq!(hist.entries(), 8);
+    assert_eq!(hist.underflow(), 2);
+    assert_eq!(hist.overflow(), 2);
+    assert_eq!(hist.nan_count(), 1);
+    assert_eq!(hist.qc_counts().in_range(), 3);
+    assert_eq!(hist.counts.iter().sum::<f64>(), 3.0);
+
+    // smoothing and normalizing leave the bookkeeping alone
+    let before = hist.qc_counts();
+    hist.smooth(0.25);
+    hist.normalize();
+    assert_eq!(hist.qc_counts(), before);
+
+    let mut pooled = hist.clone();
+    pooled.add(&hist).unwrap();
+    assert_eq!(
+        pooled.qc_counts(),
+        HistCounts {
+            entries: 16,
+            underflow: 4,
+            overflow: 4,
+            nan: 2,
+        }
+    );
+}
+
+#[test]
+fn test_qc_columns() {
+    // `f_gap` is missing for every cell of B3 and for every other cell of C4
+    let mut rng = common::Lcg::new(3);
+    let mut out = String::from("WellName\tf_a\tf_gap\n");
+    for well in common::small_plate() {
+        for cell in 0..10 {
+            let gap = if well == "B3" || (well == "C4" && cell % 2 == 0) {
+                "NA".to_string()
+            } else {
+                format!("{:.4}", rng.next_f64())
+            };
+            out.push_str(&format!("{}\t{:.4}\t{}\n", well, rng.next_f64(), gap));
+        }
+    }
+    let path = std::env::temp_dir().join("histdiff_core_qc_columns.tsv");
+    fs::write(&path, out).unwrap();
+
+    let mut config = UserConfig::new(
+        &path,
+        vec!["WellName".into()],
+        None,
+        false,
+        None,
+        None,
+        vec!["A1".into(), "B1".into()],
+        None,
+    );
+
+    let res = calculate_scores(&config).unwrap();
+    assert_eq!(res.qc["B3"]["f_gap"].nan, 10);
+    assert_eq!(res.qc["C4"]["f_gap"].nan, 5);
+    assert_eq!(res.qc["C4"]["f_gap"].entries, 10);
+    assert_eq!(res.qc["A1"]["f_a"].in_range(), 10);
+    let df = res.dataframe_scores.unwrap();
+    assert!(df.column("f_gap_nan").is_err());
+
+    config.qc_columns = true;
+    let df = calculate_scores(&config).unwrap().dataframe_scores.unwrap();
+    let names: Vec<&str> = df.get_column_names().iter().map(|n| n.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "id",
+            "f_a",
+            "f_gap",
+            "f_a_entries",
+            "f_a_underflow",
+            "f_a_overflow",
+            "f_a_nan",
+            "f_gap_entries",
+            "f_gap_underflow",
+            "f_gap_overflow",
+            "f_gap_nan",
+        ]
+    );
+
+    let ids = df.column("id").unwrap().str().unwrap();
+    let nan = df.column("f_gap_nan").unwrap().u64().unwrap();
+    for (id, nan) in ids.iter().zip(nan.iter()) {
+        let expected = match id.unwrap() {
+            "B3" => 10,
+            "C4" => 5,
+            _ => 0,
+        };
+        assert_eq!(nan, Some(expected));
+    }
+}
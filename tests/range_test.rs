@@ -0,0 +1,185 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, calculate_scores_batch, get_min_max_plate, BatchOptions, Hist1D,
+    HistDiffError, MinMax, OutOfRange, RangeCells, RangeScope, RangeStrategy, ReadMode, UserConfig,
+};
+use std::{fs, path::Path};
+
+fn range_of(config: &UserConfig, feature: &str) -> MinMax {
+    let min_max = get_min_max_plate(config).unwrap();
+    min_max
+        .min_max
+        .into_iter()
+        .find(|(feat, _)| feat == feature)
+        .map(|(_, range)| range)
+        .unwrap()
+}
+
+/// `f_a` values of a synthetic plate, sorted
+fn sorted_f_a(path: &Path) -> Vec<f64> {
+    let mut values: Vec<f64> = fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').nth(2).unwrap().parse().unwrap())
+        .collect();
+    values.sort_by(|a, b| a.total_cmp(b));
+    values
+}
+
+#[test]
+fn test_hist_out_of_range_counts() {
+    let mut separate = Hist1D::new(4, 0.0, 4.0).unwrap();
+    separate.fill(&[-1.0, 0.0, 2.5, 4.0, 7.0, 9.0, f64::NAN]);
+    assert_eq!(separate.counts, vec![1.0, 0.0, 1.0, 1.0]);
+    assert_eq!(separate.underflow(), 1);
+    assert_eq!(separate.overflow(), 2);
+
+    let mut clamped = Hist1D::new(4, 0.0, 4.0).unwrap();
+    clamped.fill_with(
+        &[-1.0, 0.0, 2.5, 4.0, 7.0, 9.0, f64::NAN],
+        OutOfRange::Clamp,
+    );
+    assert_eq!(clamped.counts, vec![2.0, 0.0, 1.0, 3.0]);
+    assert_eq!(clamped.underflow() + clamped.overflow(), 0);
+
+    separate.add(&separate.clone()).unwrap();
+    assert_eq!(separate.overflow(), 4);
+}
+
+#[test]
+fn test_percentile_ranges() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("range_percentile", &wells, 5000);
+    let mut config = common::config(&path);
+
+    config.range.strategy = RangeStrategy::Percentile {
+        low: 1.0,
+        high: 99.0,
+    };
+    let range = range_of(&config, "f_a");
+
+    // the sketch is approximate past its buffer size, allow 0.5% of rank error
+    let values = sorted_f_a(&path);
+    let at = |q: f64| values[((q * values.len() as f64) as usize).min(values.len() - 1)];
+    assert!(
+        range.xlow >= at(0.005) && range.xlow <= at(0.015),
+        "{:?}",
+        range
+    );
+    assert!(
+        range.xhigh >= at(0.985) && range.xhigh <= at(0.995),
+        "{:?}",
+        range
+    );
+
+    // both read modes see the same ranges and scores
+    let two_pass = calculate_scores(&config).unwrap().raw_scores;
+    config.read_mode = ReadMode::SinglePass;
+    let single_pass = calculate_scores(&config).unwrap().raw_scores;
+    for (well, scores) in &two_pass {
+        approx::assert_abs_diff_eq!(scores["f_a"], single_pass[well]["f_a"], epsilon = 1e-6);
+    }
+
+    config.range.strategy = RangeStrategy::Percentile {
+        low: 50.0,
+        high: 10.0,
+    };
+    assert!(matches!(
+        get_min_max_plate(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_vehicle_only_ranges() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("range_vehicles", &wells, 50);
+    let mut config = common::config(&path);
+
+    let all = range_of(&config, "f_a");
+    config.range.cells = RangeCells::Vehicles;
+    let vehicles = range_of(&config, "f_a");
+
+    // column 6 is shifted up by 2, vehicles live in column 1
+    assert!(all.xhigh > 10.0);
+    assert!(vehicles.xhigh < 10.0);
+    assert!(vehicles.xlow >= all.xlow);
+
+    let res = calculate_scores(&config).unwrap();
+    assert!(res.raw_scores["A6"]["f_a"] > 0.0);
+}
+
+#[test]
+fn test_fixed_ranges_and_clamping() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("range_fixed", &wells, 50);
+    let ranges_path = std::env::temp_dir().join("histdiff_core_range_fixed_ranges.csv");
+    fs::write(
+        &ranges_path,
+        "feature,xlow,xhigh\nf_a,2.0,6.0\nf_missing,0,1\n",
+    )
+    .unwrap();
+
+    let mut config = common::config(&path);
+    let default_b = range_of(&config, "f_b");
+
+    config.range.strategy = RangeStrategy::fixed_from_path(&ranges_path).unwrap();
+    assert_eq!(
+        range_of(&config, "f_a"),
+        MinMax {
+            xlow: 2.0,
+            xhigh: 6.0
+        }
+    );
+    assert_eq!(range_of(&config, "f_b"), default_b);
+
+    let separate = calculate_scores(&config).unwrap().raw_scores;
+    config.range.out_of_range = OutOfRange::Clamp;
+    let clamped = calculate_scores(&config).unwrap().raw_scores;
+    assert_ne!(separate["A6"]["f_a"], clamped["A6"]["f_a"]);
+    assert_eq!(separate["A6"]["f_b"], clamped["A6"]["f_b"]);
+
+    fs::write(&ranges_path, "feature,xlow,xhigh\nf_a,6.0,2.0\n").unwrap();
+    assert!(matches!(
+        RangeStrategy::fixed_from_path(&ranges_path),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+
+    fs::write(&ranges_path, "feature,low,xhigh\nf_a,2.0,6.0\n").unwrap();
+    match RangeStrategy::fixed_from_path(&ranges_path) {
+        Err(HistDiffError::MissingColumn { path, column }) => {
+            assert_eq!(path, ranges_path);
+            assert_eq!(column, "xlow");
+        }
+        other => panic!("expected a missing column error, got {:?}", other.is_ok()),
+    }
+}
+
+#[test]
+fn test_campaign_percentile_ranges() {
+    let dir = std::env::temp_dir().join("histdiff_core_range_campaign");
+    fs::create_dir_all(&dir).unwrap();
+    let wells = common::small_plate();
+    let plates: Vec<_> = (0..3)
+        .map(|i| {
+            let path = dir.join(format!("plate_{}.tsv", i));
+            common::write_plate_tsv_to(&path, &wells, 30, i as u64, 1.0 + i as f64);
+            path
+        })
+        .collect();
+
+    let mut config = common::config(&plates[0]);
+    config.range.strategy = RangeStrategy::Percentile {
+        low: 0.5,
+        high: 99.5,
+    };
+    let options = BatchOptions {
+        range_scope: RangeScope::Campaign,
+        ..BatchOptions::default()
+    };
+
+    let res = calculate_scores_batch(&plates, &config, &options).unwrap();
+    assert_eq!(res.raw_scores.len(), 3 * 24);
+}
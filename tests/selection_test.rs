@@ -0,0 +1,168 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, calculate_scores_df, get_min_max_plate, ExcludedColumn, ExclusionReason,
+    FeatureSelector, HistDiffError, ReadMode,
+};
+use polars::prelude::*;
+use std::fs;
+
+/// Synthetic plate with an extra `MeasurementDate` metadata column
+fn write_plate_with_metadata(name: &str) -> std::path::PathBuf {
+    let path = common::write_plate_tsv(name, &common::small_plate(), 20);
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .enumerate()
+        .map(|(i, line)| match i {
+            0 => format!("{}\tMeasurementDate\n", line),
+            _ => format!("{}\t2024-05-0{}\n", line, 1 + i % 9),
+        })
+        .collect();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn reason_of<'a>(excluded: &'a [ExcludedColumn], column: &str) -> Option<&'a ExclusionReason> {
+    excluded
+        .iter()
+        .find(|c| c.column == column)
+        .map(|c| &c.reason)
+}
+
+#[test]
+fn test_non_numeric_columns_are_excluded() {
+    let path = write_plate_with_metadata("selection_infer");
+    let mut config = common::config(&path);
+
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert_eq!(ranges.features, vec!["f_a", "f_b", "f_const"]);
+    assert_eq!(
+        reason_of(&ranges.excluded_columns, "MeasurementDate"),
+        Some(&ExclusionReason::NonNumeric {
+            value: "2024-05-02".to_string()
+        })
+    );
+    assert_eq!(
+        reason_of(&ranges.excluded_columns, "PlateName"),
+        Some(&ExclusionReason::Useless)
+    );
+    // all missing is still numeric, it ends up as a problematic feature
+    assert_eq!(reason_of(&ranges.excluded_columns, "f_empty"), None);
+
+    let res = calculate_scores(&config).unwrap();
+    assert_eq!(res.excluded_columns, ranges.excluded_columns);
+    assert!(!res.raw_scores["A6"].contains_key("MeasurementDate"));
+
+    config.selection.infer_types = false;
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert!(ranges
+        .problematic_features
+        .iter()
+        .any(|p| p.feature == "MeasurementDate"));
+}
+
+#[test]
+fn test_include_and_exclude_selectors() {
+    let path = write_plate_with_metadata("selection_patterns");
+    let mut config = common::config(&path);
+    config.selection.include = vec![
+        FeatureSelector::pattern("^f_").unwrap(),
+        FeatureSelector::name("MeasurementDate"),
+    ];
+    config.selection.exclude = vec![
+        FeatureSelector::name("f_b"),
+        FeatureSelector::pattern("_(const|empty)$").unwrap(),
+    ];
+
+    for read_mode in [ReadMode::TwoPass, ReadMode::SinglePass] {
+        config.read_mode = read_mode;
+        let res = calculate_scores(&config).unwrap();
+        let mut feats: Vec<&String> = res.raw_scores["A6"].keys().collect();
+        feats.sort();
+        assert_eq!(feats, vec!["f_a"]);
+
+        let excluded = &res.excluded_columns;
+        assert_eq!(
+            reason_of(excluded, "f_b"),
+            Some(&ExclusionReason::Excluded {
+                selector: "f_b".to_string()
+            })
+        );
+        assert_eq!(
+            reason_of(excluded, "f_const"),
+            Some(&ExclusionReason::Excluded {
+                selector: "/_(const|empty)$/".to_string()
+            })
+        );
+        assert!(matches!(
+            reason_of(excluded, "MeasurementDate"),
+            Some(ExclusionReason::NonNumeric { .. })
+        ));
+    }
+
+    config.selection.include = vec![FeatureSelector::name("f_a")];
+    config.selection.exclude.clear();
+    let ranges = get_min_max_plate(&config).unwrap();
+    assert_eq!(ranges.features, vec!["f_a"]);
+    assert_eq!(
+        reason_of(&ranges.excluded_columns, "f_b"),
+        Some(&ExclusionReason::NotIncluded)
+    );
+
+    assert!(matches!(
+        FeatureSelector::pattern("f_["),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_dataframe_column_types() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("selection_df", &wells, 20);
+    let mut df = common::read_plate_df(&path);
+    let height = df.height();
+    df.with_column(Column::new("Operator".into(), vec!["someone"; height]))
+        .unwrap();
+    df.with_column(Column::new("Flag".into(), vec![true; height]))
+        .unwrap();
+
+    let mut config = common::config(&path);
+    config.plate_def = wells;
+    let res = calculate_scores_df(&df, &config).unwrap();
+    assert_eq!(
+        reason_of(&res.excluded_columns, "Operator"),
+        Some(&ExclusionReason::NonNumeric {
+            value: "someone".to_string()
+        })
+    );
+    assert_eq!(reason_of(&res.excluded_columns, "Flag"), None);
+    assert!(res.raw_scores["A6"].contains_key("f_a"));
+}
+
+#[test]
+fn test_padded_numbers_are_numeric() {
+    let path = common::write_plate_tsv("selection_padded", &common::small_plate(), 20);
+    let plain = calculate_scores(&common::config(&path)).unwrap();
+
+    let content: String = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut cols: Vec<String> = line.split('\t').map(|c| c.to_string()).collect();
+            if i > 0 {
+                cols[2] = format!(" {} ", cols[2]);
+            }
+            format!("{}\n", cols.join("\t"))
+        })
+        .collect();
+    fs::write(&path, content).unwrap();
+
+    let padded = calculate_scores(&common::config(&path)).unwrap();
+    assert_eq!(reason_of(&padded.excluded_columns, "f_a"), None);
+    assert_eq!(
+        padded.raw_scores["A6"]["f_a"],
+        plain.raw_scores["A6"]["f_a"]
+    );
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use histdiff_core::{
+    benjamini_hochberg, calculate_scores, empirical_p_value, HistDiffError, SignificanceMethod,
+    SignificanceOptions,
+};
+
+#[test]
+fn test_p_and_q_values() {
+    assert_eq!(empirical_p_value(2.0, &[-3.0, 1.0, 0.5, 2.0]), 0.6);
+    assert!(empirical_p_value(f64::NAN, &[1.0]).is_nan());
+
+    let q = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.2, f64::NAN]);
+    approx::assert_abs_diff_eq!(q[0], 0.04, epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(q[1], 0.16 / 3.0, epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(q[2], 0.16 / 3.0, epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(q[3], 0.2, epsilon = 1e-12);
+    assert!(q[4].is_nan());
+}
+
+#[test]
+fn test_resampling_significance() {
+    let path = common::write_plate_tsv("significance", &common::small_plate(), 50);
+    let mut config = common::config(&path);
+
+    let plain = calculate_scores(&config).unwrap();
+    assert!(plain.p_values.is_empty());
+
+    for method in [
+        SignificanceMethod::Permutation,
+        SignificanceMethod::Bootstrap,
+    ] {
+        let options = SignificanceOptions {
+            method,
+            iterations: 200,
+            seed: 7,
+        };
+        config.significance = Some(options);
+        let res = calculate_scores(&config).unwrap();
+        assert_eq!(res.raw_scores, plain.raw_scores);
+
+        // column 6 is shifted, nothing in the null comes close
+        approx::assert_abs_diff_eq!(res.p_values["A6"]["f_a"], 1.0 / 201.0);
+        assert!(res.q_values["A6"]["f_a"] < 0.05);
+        assert!(res.p_values["B3"]["f_a"] > 0.05);
+        assert_eq!(res.p_values["C2"]["f_const"], 1.0);
+        for (well, p) in &res.p_values {
+            assert!(p["f_a"] <= res.q_values[well]["f_a"]);
+        }
+
+        // the same seed gives the same p-values, another seed does not
+        assert_eq!(calculate_scores(&config).unwrap().p_values, res.p_values);
+        config.significance = Some(SignificanceOptions { seed: 8, ..options });
+        assert_ne!(calculate_scores(&config).unwrap().p_values, res.p_values);
+
+        let df = res.dataframe_scores.unwrap();
+        let p = df.column("f_b_p").unwrap().f64().unwrap();
+        let q = df.column("f_b_q").unwrap().f64().unwrap();
+        assert!(p
+            .iter()
+            .zip(q.iter())
+            .all(|(p, q)| p.unwrap() <= q.unwrap()));
+    }
+
+    config.significance = Some(SignificanceOptions {
+        iterations: 0,
+        ..Default::default()
+    });
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
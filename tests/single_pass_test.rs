@@ -0,0 +1,32 @@
+mod common;
+
+use approx::assert_relative_eq;
+use histdiff_core::{calculate_scores, ReadMode};
+
+#[test]
+fn test_single_pass_matches_two_pass() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("single_pass", &wells, 50);
+
+    let mut config =
+        common::plate_config(&path, None, Some(wells.clone()), &["A1", "B1", "C1", "D1"]);
+
+    let two_pass = calculate_scores(&config).expect("two pass failed");
+
+    config.read_mode = ReadMode::SinglePass;
+    let single_pass = calculate_scores(&config).expect("single pass failed");
+
+    assert_eq!(two_pass.raw_scores.len(), wells.len());
+    assert_eq!(two_pass.raw_scores.len(), single_pass.raw_scores.len());
+
+    for (well, feats) in &two_pass.raw_scores {
+        let other = single_pass.raw_scores.get(well).expect("missing well");
+        assert_eq!(feats.len(), other.len());
+        for (feat, score) in feats {
+            assert_relative_eq!(*score, other[feat], epsilon = 1e-6);
+        }
+    }
+
+    // the shifted column should score away from the vehicles
+    assert!(two_pass.raw_scores["A6"]["f_a"] > 0.0);
+}
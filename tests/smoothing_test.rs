@@ -0,0 +1,106 @@
+mod common;
+
+use approx::assert_abs_diff_eq;
+use histdiff_core::{calculate_scores, Hist1D, HistDiffError, Smoothing};
+
+fn hist_with(counts: &[f64]) -> Hist1D {
+    let mut hist = Hist1D::new(counts.len(), 0.0, counts.len() as f64).unwrap();
+    hist.counts = counts.to_vec();
+    hist
+}
+
+#[test]
+fn test_smoothing_kernels() {
+    let counts = [0.0, 0.0, 3.0, 0.0, 0.0];
+
+    let mut exp = hist_with(&counts);
+    let mut legacy = hist_with(&counts);
+    exp.smooth_with(&Smoothing::default());
+    legacy.smooth(0.25);
+    assert_eq!(exp.counts, legacy.counts);
+
+    let mut none = hist_with(&counts);
+    none.smooth_with(&Smoothing::None);
+    assert_eq!(none.counts, counts);
+
+    let mut avg = hist_with(&counts);
+    avg.smooth_with(&Smoothing::MovingAverage { k: 3 });
+    for (got, want) in avg.counts.iter().zip([0.0, 1.0, 1.0, 1.0, 0.0]) {
+        assert_abs_diff_eq!(*got, want, epsilon = 1e-12);
+    }
+
+    let mut gauss = hist_with(&counts);
+    gauss.smooth_with(&Smoothing::Gaussian { sigma: 1.0 });
+    assert!(gauss.counts[2] < 3.0 && gauss.counts[2] > gauss.counts[1]);
+    assert_abs_diff_eq!(gauss.counts[1], gauss.counts[3], epsilon = 1e-12);
+    assert!(gauss.counts[0] > 0.0);
+
+    // a kernel far wider than the histogram flattens it instead of allocating the full kernel
+    let mut flat = hist_with(&counts);
+    flat.smooth_with(&Smoothing::Gaussian { sigma: 1e12 });
+    for got in &flat.counts {
+        assert_abs_diff_eq!(*got, 0.6, epsilon = 1e-9);
+    }
+
+    // a quadratic is reproduced exactly by an order 2 fit, edges included
+    let quadratic: Vec<f64> = (0..8).map(|i| 1.0 + (i as f64 - 3.0).powi(2)).collect();
+    let mut sg = hist_with(&quadratic);
+    sg.smooth_with(&Smoothing::SavitzkyGolay {
+        window: 5,
+        order: 2,
+    });
+    for (got, want) in sg.counts.iter().zip(&quadratic) {
+        assert_abs_diff_eq!(*got, *want, epsilon = 1e-9);
+    }
+}
+
+#[test]
+fn test_smoothing_validation() {
+    let invalid = [
+        Smoothing::Exponential { alpha: 0.8 },
+        Smoothing::Gaussian { sigma: 0.0 },
+        Smoothing::MovingAverage { k: 0 },
+        Smoothing::SavitzkyGolay {
+            window: 4,
+            order: 2,
+        },
+        Smoothing::SavitzkyGolay {
+            window: 3,
+            order: 3,
+        },
+    ];
+    for smoothing in invalid {
+        assert!(smoothing.validate().is_err(), "{:?}", smoothing);
+    }
+    assert!(Smoothing::default().validate().is_ok());
+
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("smoothing_invalid", &wells, 10);
+    let mut config = common::vehicle_config(&path, &["A1"]);
+    config.smoothing = Smoothing::Gaussian { sigma: -1.0 };
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_config_smoothing_changes_scores() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("smoothing_scores", &wells, 10);
+    let mut config = common::config(&path);
+
+    let smoothed = calculate_scores(&config).unwrap().raw_scores;
+    config.smoothing = Smoothing::None;
+    let raw = calculate_scores(&config).unwrap().raw_scores;
+    config.smoothing = Smoothing::Gaussian { sigma: 2.0 };
+    let gaussian = calculate_scores(&config).unwrap().raw_scores;
+
+    // heavier smoothing pulls sparse wells closer to the pooled control
+    let total = |scores: &std::collections::HashMap<
+        String,
+        std::collections::HashMap<String, f64>,
+    >| { scores.values().map(|s| s["f_a"].abs()).sum::<f64>() };
+    assert!(total(&gaussian) < total(&smoothed));
+    assert!(total(&smoothed) < total(&raw));
+}
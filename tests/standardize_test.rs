@@ -0,0 +1,66 @@
+mod common;
+
+use histdiff_core::{calculate_scores, robust_z_scores, MAD_SCALE};
+
+#[test]
+fn test_robust_z_scores() {
+    let z = robust_z_scores(
+        &[3.0 + MAD_SCALE, 3.0, f64::NAN],
+        &[1.0, 2.0, 3.0, 4.0, 100.0],
+    );
+    approx::assert_abs_diff_eq!(z[0], 1.0, epsilon = 1e-12);
+    assert_eq!(z[1], 0.0);
+    assert!(z[2].is_nan());
+
+    // no spread in the null
+    let z = robust_z_scores(&[2.0, 3.0], &[2.0, 2.0, f64::NAN, 2.0]);
+    assert_eq!(z[0], 0.0);
+    assert!(z[1].is_nan());
+    assert!(robust_z_scores(&[1.0], &[])[0].is_nan());
+}
+
+#[test]
+fn test_vehicle_null_and_z_scores() {
+    let path = common::write_plate_tsv("standardize", &common::small_plate(), 50);
+    let mut config = common::vehicle_config(&path, &["A1", "B1", "C1", "D1"]);
+
+    let raw = calculate_scores(&config).unwrap();
+    assert!(raw.z_scores.is_empty());
+    assert!(raw.dataframe_z_scores.is_none());
+
+    config.standardize = true;
+    let res = calculate_scores(&config).unwrap();
+    assert_eq!(res.raw_scores, raw.raw_scores);
+
+    let mut null_wells: Vec<&String> = res.vehicle_null.keys().collect();
+    null_wells.sort();
+    assert_eq!(null_wells, vec!["A1", "B1", "C1", "D1"]);
+
+    // A1 against the pool of the other three vehicles
+    let others = calculate_scores(&common::vehicle_config(&path, &["B1", "C1", "D1"])).unwrap();
+    approx::assert_abs_diff_eq!(
+        res.vehicle_null["A1"]["f_a"],
+        others.raw_scores["A1"]["f_a"],
+        epsilon = 1e-12
+    );
+
+    assert_eq!(res.z_scores.len(), 24);
+    assert!(res.z_scores["A6"]["f_a"] > 3.0);
+    assert_eq!(res.z_scores["B4"]["f_const"], 0.0);
+
+    let df = res.dataframe_scores.unwrap();
+    let z_df = res.dataframe_z_scores.unwrap();
+    assert_eq!(z_df.shape(), df.shape());
+    assert_eq!(z_df.get_column_names(), df.get_column_names());
+}
+
+#[test]
+fn test_single_vehicle_has_no_null() {
+    let path = common::write_plate_tsv("standardize_single", &common::small_plate(), 20);
+    let mut config = common::vehicle_config(&path, &["A1"]);
+    config.standardize = true;
+
+    let res = calculate_scores(&config).unwrap();
+    assert!(res.vehicle_null.is_empty());
+    assert!(res.z_scores["A6"]["f_a"].is_nan());
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use approx::assert_relative_eq;
+use flate2::{write::GzEncoder, Compression as GzLevel};
+use histdiff_core::{calculate_scores, HistDiffRes, TextOptions, UserConfig};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+fn config_for(path: &Path, wells: &[String]) -> UserConfig {
+    common::plate_config(path, None, Some(wells.to_vec()), &["A1", "B1"])
+}
+
+fn assert_same_scores(expected: &HistDiffRes, res: &HistDiffRes) {
+    assert_eq!(expected.raw_scores.len(), res.raw_scores.len());
+    for (well, feats) in &expected.raw_scores {
+        for (feat, score) in feats {
+            assert_relative_eq!(*score, res.raw_scores[well][feat], epsilon = 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_gzip_with_preamble() {
+    let wells = common::small_plate();
+    let tsv = common::write_plate_tsv("text_gz", &wells, 30);
+    let expected = calculate_scores(&config_for(&tsv, &wells)).unwrap();
+
+    let gz = std::env::temp_dir().join("histdiff_core_text_gz.tsv.gz");
+    let mut encoder = GzEncoder::new(File::create(&gz).unwrap(), GzLevel::default());
+    encoder
+        .write_all(b"Vendor Export v2\nExported\tyesterday\n")
+        .unwrap();
+    encoder.write_all(&fs::read(&tsv).unwrap()).unwrap();
+    encoder.finish().unwrap();
+
+    let mut config = config_for(&gz, &wells);
+    config.text_options.skip_lines = 2;
+
+    assert_same_scores(&expected, &calculate_scores(&config).unwrap());
+}
+
+#[test]
+fn test_zstd_comma_separated_with_comments() {
+    let wells = common::small_plate();
+    let tsv = common::write_plate_tsv("text_zst", &wells, 30);
+    let expected = calculate_scores(&config_for(&tsv, &wells)).unwrap();
+
+    let mut csv = String::from("# exported by the plate reader\n");
+    for (i, line) in fs::read_to_string(&tsv).unwrap().lines().enumerate() {
+        let fields: Vec<String> = line.split('\t').map(|f| format!("'{}'", f)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+        if i == 10 {
+            csv.push_str("# mid-file comment\n");
+        }
+    }
+
+    let zst = std::env::temp_dir().join("histdiff_core_text_zst.csv.zst");
+    fs::write(&zst, zstd::encode_all(csv.as_bytes(), 3).unwrap()).unwrap();
+
+    let mut config = config_for(&zst, &wells);
+    config.text_options = TextOptions {
+        delimiter: b',',
+        quote: b'\'',
+        comment: Some(b'#'),
+        ..TextOptions::default()
+    };
+
+    assert_same_scores(&expected, &calculate_scores(&config).unwrap());
+}
+
+#[test]
+fn test_headerless_columns() {
+    let wells = common::small_plate();
+    let tsv = common::write_plate_tsv("text_headerless", &wells, 30);
+    let expected = calculate_scores(&config_for(&tsv, &wells)).unwrap();
+
+    let body: String = fs::read_to_string(&tsv)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|l| format!("{}\n", l))
+        .collect();
+    let headerless = std::env::temp_dir().join("histdiff_core_text_headerless.tsv");
+    fs::write(&headerless, body).unwrap();
+
+    let mut config = config_for(&headerless, &wells);
+    config.id_cols = vec!["column_2".into()];
+    config.useless_cols = Some(vec!["column_1".into()]);
+    config.text_options.has_headers = false;
+
+    let res = calculate_scores(&config).unwrap();
+    assert_eq!(res.raw_scores.len(), expected.raw_scores.len());
+    for (well, feats) in &expected.raw_scores {
+        assert_relative_eq!(
+            feats["f_a"],
+            res.raw_scores[well]["column_3"],
+            epsilon = 1e-9
+        );
+    }
+}
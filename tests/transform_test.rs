@@ -0,0 +1,108 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, get_min_max_plate, FeatureTransform, HistDiffError, MinMax, ReadMode,
+    Transform, UserConfig,
+};
+
+fn range_of(config: &UserConfig, feature: &str) -> MinMax {
+    get_min_max_plate(config)
+        .unwrap()
+        .min_max
+        .into_iter()
+        .find(|(feat, _)| feat == feature)
+        .map(|(_, range)| range)
+        .unwrap()
+}
+
+#[test]
+fn test_transform_values() {
+    assert_eq!(Transform::Log1p.apply(0.0), 0.0);
+    approx::assert_abs_diff_eq!(
+        Transform::Log10 { offset: 1.0 }.apply(99.0),
+        2.0,
+        epsilon = 1e-12
+    );
+    approx::assert_abs_diff_eq!(
+        Transform::Arcsinh { cofactor: 5.0 }.apply(-5.0),
+        -(1.0f64.asinh()),
+        epsilon = 1e-12
+    );
+    assert_eq!(Transform::Sqrt.apply(16.0), 4.0);
+    approx::assert_abs_diff_eq!(
+        Transform::BoxCox { lambda: 0.0 }.apply(std::f64::consts::E),
+        1.0,
+        epsilon = 1e-12
+    );
+    assert_eq!(Transform::BoxCox { lambda: 2.0 }.apply(3.0), 4.0);
+
+    // out of domain values are missing, not infinite
+    assert!(Transform::Sqrt.apply(-1.0).is_nan());
+    assert!(Transform::Log10 { offset: 0.0 }.apply(0.0).is_nan());
+    assert!(Transform::BoxCox { lambda: 0.5 }.apply(-2.0).is_nan());
+    assert_eq!(Transform::Log1p.apply(f64::INFINITY), f64::INFINITY);
+}
+
+#[test]
+fn test_ranges_on_transformed_scale() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("transform_ranges", &wells, 50);
+    let mut config = common::config(&path);
+
+    let raw_a = range_of(&config, "f_a");
+
+    config.transforms = vec![
+        FeatureTransform::named("f_a", Transform::Sqrt),
+        FeatureTransform::matching("^f_", Transform::Log1p).unwrap(),
+    ];
+    let sqrt_a = range_of(&config, "f_a");
+    approx::assert_abs_diff_eq!(sqrt_a.xlow, raw_a.xlow.sqrt(), epsilon = 1e-12);
+    approx::assert_abs_diff_eq!(sqrt_a.xhigh, raw_a.xhigh.sqrt(), epsilon = 1e-12);
+    // the constant is widened on the transformed scale
+    approx::assert_abs_diff_eq!(
+        range_of(&config, "f_const").xlow,
+        3.5f64.ln_1p() - 0.5,
+        epsilon = 1e-12
+    );
+
+    let two_pass = calculate_scores(&config).unwrap().raw_scores;
+    config.read_mode = ReadMode::SinglePass;
+    let single_pass = calculate_scores(&config).unwrap().raw_scores;
+    for (well, scores) in &two_pass {
+        approx::assert_abs_diff_eq!(scores["f_a"], single_pass[well]["f_a"], epsilon = 1e-6);
+    }
+}
+
+#[test]
+fn test_out_of_domain_values_are_nan() {
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("transform_domain", &wells, 50);
+    let mut config = common::config(&path);
+
+    // f_b is shifted below 0 in column 6
+    config.transforms = vec![FeatureTransform::named("f_b", Transform::Sqrt)];
+    let res = calculate_scores(&config).unwrap();
+    assert!(res.qc["A6"]["f_b"].nan > 0);
+    assert_eq!(res.qc["A1"]["f_b"].nan, 0);
+    assert!(range_of(&config, "f_b").xlow >= 0.0);
+}
+
+#[test]
+fn test_invalid_transforms() {
+    assert!(matches!(
+        FeatureTransform::matching("f_(", Transform::Sqrt),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+
+    let wells = common::small_plate();
+    let path = common::write_plate_tsv("transform_invalid", &wells, 5);
+    let mut config = common::config(&path);
+    config.transforms = vec![FeatureTransform::named(
+        "f_a",
+        Transform::Arcsinh { cofactor: 0.0 },
+    )];
+    assert!(matches!(
+        get_min_max_plate(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}
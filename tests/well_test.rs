@@ -0,0 +1,184 @@
+mod common;
+
+use histdiff_core::{
+    calculate_scores, calculate_scores_df, normalize_well, HistDiffError, IdMode, PlateLayout,
+    UserConfig, WellId,
+};
+use std::{fs, path::PathBuf};
+
+#[test]
+fn test_well_id_parsing() {
+    assert_eq!(WellId::parse("A1").unwrap(), WellId::new(1, 1));
+    assert_eq!(WellId::parse("A01").unwrap(), WellId::new(1, 1));
+    assert_eq!(WellId::parse(" p24 ").unwrap(), WellId::new(16, 24));
+    assert_eq!(WellId::parse("AA48").unwrap(), WellId::new(27, 48));
+    assert_eq!(WellId::parse("AF01").unwrap(), WellId::new(32, 1));
+
+    for bad in ["", "A", "12", "A0", "A1B", "3_12"] {
+        assert!(WellId::parse(bad).is_err(), "{} should not parse", bad);
+    }
+
+    assert_eq!(WellId::new(32, 7).to_string(), "AF7");
+    assert_eq!(WellId::new(26, 7).to_string(), "Z7");
+    assert_eq!(WellId::new(2, 3).padded(2), "B03");
+    assert_eq!(normalize_well("af07"), "AF7");
+    assert_eq!(normalize_well("plate_1_A01"), "plate_1_A01");
+}
+
+#[test]
+fn test_plate_layouts() {
+    let cases = [
+        (PlateLayout::Wells96, 96, "H12"),
+        (PlateLayout::Wells384, 384, "P24"),
+        (PlateLayout::Wells1536, 1536, "AF48"),
+        (PlateLayout::Custom { rows: 3, cols: 5 }, 15, "C5"),
+    ];
+
+    for (layout, count, last) in cases {
+        let wells = layout.wells();
+        assert_eq!(wells.len(), count);
+        assert_eq!(wells[0], "A1");
+        assert_eq!(wells.last().unwrap(), last);
+        assert!(layout.contains(&WellId::parse(last).unwrap()));
+        assert!(!layout.contains(&WellId::new(layout.rows() + 1, 1)));
+    }
+
+    assert_eq!(
+        PlateLayout::from_well_count(1536),
+        Some(PlateLayout::Wells1536)
+    );
+    assert_eq!(PlateLayout::from_well_count(100), None);
+}
+
+#[test]
+fn test_config_normalizes_wells() {
+    let config = UserConfig::new(
+        "unused.tsv",
+        vec!["WellName".into()],
+        None,
+        false,
+        Some(vec![vec!["AA01".into(), "af48".into()]]),
+        Some(PlateLayout::Wells1536.wells()),
+        vec!["A01".into(), "B02".into()],
+        None,
+    );
+
+    assert_eq!(config.vehicle_cntrls, vec!["A1", "B2"]);
+    assert_eq!(config.plate_def.len(), 1536);
+    assert_eq!(config.block_def.len(), 2);
+    assert_eq!(config.block_def[1].len(), 1534);
+}
+
+#[test]
+fn test_padded_data_wells_match_config() {
+    let wells = common::small_plate();
+    let padded: Vec<String> = wells
+        .iter()
+        .map(|w| WellId::parse(w).unwrap().padded(2))
+        .collect();
+
+    let plain_path = common::write_plate_tsv("wells_plain", &wells, 20);
+    let padded_path = common::write_plate_tsv("wells_padded", &padded, 20);
+
+    let config = |path| {
+        UserConfig::new(
+            path,
+            vec!["WellName".into()],
+            common::useless_cols(),
+            false,
+            None,
+            None,
+            vec![
+                "A01".into(),
+                "B1".into(),
+                "C01".into(),
+                "D1".into(),
+                "P24".into(),
+            ],
+            None,
+        )
+    };
+
+    let plain = calculate_scores(&config(plain_path)).unwrap();
+    let padded = calculate_scores(&config(padded_path)).unwrap();
+
+    assert_eq!(padded.raw_scores.len(), 24);
+    assert!(padded.raw_scores.contains_key("A1"));
+    assert_eq!(plain.raw_scores, padded.raw_scores);
+
+    assert_eq!(padded.unseen_vehicles, vec!["P24"]);
+    assert_eq!(padded.unseen_wells.len(), 384 - 24);
+    assert_eq!(padded.unseen_wells[0], "A7");
+    assert_eq!(padded.unseen_wells.last().unwrap(), "P24");
+}
+
+/// Rewrites a synthetic plate so wells are given as numeric `Row`/`Column` columns
+fn write_row_column_tsv(name: &str, source: &std::path::Path, zero_based: bool) -> PathBuf {
+    let offset = if zero_based { 1 } else { 0 };
+    let text = fs::read_to_string(source).unwrap();
+    let mut out = String::from("PlateName\tRow\tColumn\tf_a\tf_b\tf_const\tf_empty\n");
+    for line in text.lines().skip(1) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let well = WellId::parse(fields[1]).unwrap();
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            fields[0],
+            well.row - offset,
+            well.col - offset,
+            fields[2..].join("\t")
+        ));
+    }
+
+    let path = std::env::temp_dir().join(format!("histdiff_core_{}.tsv", name));
+    fs::write(&path, out).unwrap();
+    path
+}
+
+#[test]
+fn test_row_column_id_mode() {
+    let wells = common::small_plate();
+    let named_path = common::write_plate_tsv("wells_named", &wells, 20);
+    let vehicles: Vec<String> = vec!["A1".into(), "B1".into(), "C1".into(), "D1".into()];
+
+    let named = UserConfig::new(
+        &named_path,
+        vec!["WellName".into()],
+        common::useless_cols(),
+        false,
+        None,
+        None,
+        vehicles.clone(),
+        None,
+    );
+    let expected = calculate_scores(&named).unwrap().raw_scores;
+
+    for zero_based in [false, true] {
+        let path = write_row_column_tsv("wells_row_column", &named_path, zero_based);
+        let mut config = UserConfig::new(
+            &path,
+            vec!["Row".into(), "Column".into()],
+            common::useless_cols(),
+            false,
+            None,
+            None,
+            vehicles.clone(),
+            None,
+        );
+        config.id_mode = IdMode::RowColumn { zero_based };
+
+        let scores = calculate_scores(&config).unwrap().raw_scores;
+        assert_eq!(scores, expected, "zero_based = {}", zero_based);
+
+        // numeric columns of a DataFrame go through the same path
+        let df = common::read_plate_df(&path);
+        let scores = calculate_scores_df(&df, &config).unwrap().raw_scores;
+        assert_eq!(scores, expected, "zero_based = {}", zero_based);
+    }
+
+    let mut config = named;
+    config.id_mode = IdMode::RowColumn { zero_based: false };
+    assert!(matches!(
+        calculate_scores(&config),
+        Err(HistDiffError::InvalidConfig(_))
+    ));
+}